@@ -0,0 +1,90 @@
+//! Measures the throughput of the slots by executing tens of thousands of trivial jobs.
+//!
+//! Run with `cargo bench --bench slots`.
+
+extern crate concurr;
+extern crate num_cpus;
+
+use concurr::{slot_event, Execution, InsertJob, InsertOutput, Mailbox, Mode, Queue, Running,
+              Status, Tokens};
+use std::fs::File;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::thread;
+use std::time::Instant;
+
+const JOBS: usize = 20_000;
+
+struct Inputs {
+    inputs: Queue<(usize, Vec<String>)>,
+}
+
+impl InsertJob for Inputs {
+    fn get_job(&self) -> Option<(usize, Vec<String>)> { self.inputs.pop() }
+
+    fn insert_job(&self, id: usize, job: Vec<String>) { self.inputs.push_back((id, job)); }
+}
+
+struct Outputs {
+    outputs: Mailbox<Option<Status>>,
+}
+
+impl InsertOutput for Outputs {
+    fn insert(&self, id: usize, result: Option<(Execution, File, File)>) {
+        self.outputs.insert(id, result.map(|(execution, _, _)| execution.status));
+    }
+}
+
+fn bench(command: &str, mode: Mode) {
+    let command = Tokens::with_mode(command, mode);
+    let inputs = Arc::new(Inputs {
+        inputs: Queue::new(),
+    });
+    let outputs = Arc::new(Outputs {
+        outputs: Mailbox::new(),
+    });
+    let parked = Arc::new(AtomicUsize::new(0));
+    let running = Arc::new(Running::new());
+
+    let slots = num_cpus::get();
+    let handles = (0..slots)
+        .map(|slot| {
+            let command = command.clone();
+            let inputs = inputs.clone();
+            let outputs = outputs.clone();
+            let parked = parked.clone();
+            let running = running.clone();
+            thread::spawn(move || slot_event(slot, command, inputs, outputs, parked, running, None))
+        })
+        .collect::<Vec<_>>();
+
+    let start = Instant::now();
+    for id in 0..JOBS {
+        inputs.insert_job(id, vec![id.to_string()]);
+    }
+
+    let failed = (0..JOBS)
+        .filter(|&id| outputs.outputs.remove(id) != Some(Status::Exited(0)))
+        .count();
+    let time = Instant::now() - start;
+
+    inputs.inputs.close();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+
+    let seconds = time.as_secs() as f64 + f64::from(time.subsec_nanos()) / 1e9;
+    println!(
+        "{:?} '{}': {} jobs on {} slots in {:.3}s ({:.0} jobs/s, {} failed)",
+        mode,
+        command,
+        JOBS,
+        slots,
+        seconds,
+        JOBS as f64 / seconds,
+        failed
+    );
+}
+
+fn main() {
+    bench("true", Mode::Shell);
+    bench("true", Mode::Exec);
+}
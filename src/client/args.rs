@@ -1,7 +1,16 @@
+use concurr::{Limit, Mode, Timeout, Tokens};
+use halt::HaltPolicy;
+use inputs::MAX_PENDING;
+use joblog::Resume;
+use outputs::OutputMode;
+use progress;
 use redirection::{self, RedirectionSource};
+use source::{Delimiter, Format};
 use std::env::args;
 use std::fmt::{self, Display, Formatter};
 use std::path::PathBuf;
+use std::str::FromStr;
+use summary::ExitPolicy;
 
 #[derive(Debug, PartialEq)]
 pub enum ArgsSource {
@@ -21,6 +30,13 @@ pub enum ArgumentError {
     NoCommand,
     NoInputs,
     Invalid(String),
+    InvalidOption(String),
+    NoValue(&'static str),
+    InvalidValue(&'static str, String),
+    /// The first option may only be supplied alongside the second.
+    Requires(&'static str, &'static str),
+    /// The first option may not be supplied alongside the second.
+    Conflicts(&'static str, &'static str),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -32,8 +48,42 @@ pub enum Action {
 
 #[derive(Debug, PartialEq)]
 pub struct Arguments {
-    command:  String,
-    pub args: ArgsSource,
+    command:         String,
+    pub args:        ArgsSource,
+    /// Splits each line of input into columns, which are referenced with `{1}`, `{2}`, etc.
+    pub colsep:      Option<String>,
+    /// How the inputs within files and stdin are separated, and which are skipped.
+    pub format:      Format,
+    /// Whether the command is executed through the shell, or directly.
+    pub mode:        Mode,
+    /// How the exit status is derived from the exit statuses of each job.
+    pub exit_policy: ExitPolicy,
+    /// Stops dispatching inputs once enough jobs have failed, or have succeeded.
+    pub halt:        Option<HaltPolicy>,
+    /// Kills jobs which run for too long.
+    pub timeout:     Option<Timeout>,
+    /// The number of times that a job which did not succeed will be attempted again.
+    pub retries:     u8,
+    /// Whether failed jobs should preferably be retried on a different node.
+    pub elsewhere:   bool,
+    /// A file which records the timing, host, and exit status of every job.
+    pub joblog:      Option<PathBuf>,
+    /// Skips the jobs which completed in the run recorded by the job log.
+    pub resume:      Option<Resume>,
+    /// Displays the progress of the jobs on stderr.
+    pub progress:    Option<progress::Mode>,
+    /// Stores the outputs of each job within this directory, rather than writing them.
+    pub results:     Option<PathBuf>,
+    /// When, and in what order, the outputs of each job are written.
+    pub output_mode: OutputMode,
+    /// A template which precedes each line of the outputs of each job.
+    pub tag:         Option<Tokens>,
+    /// The number of bytes of each output of a job that are kept.
+    pub max_output:  Option<Limit>,
+    /// The number of bytes of outputs from nodes that may wait in memory to be written.
+    pub max_memory:  Limit,
+    /// The number of inputs that may wait to be processed before the readers of inputs park.
+    pub max_pending: usize,
 }
 
 impl Display for ArgumentError {
@@ -42,66 +92,189 @@ impl Display for ArgumentError {
             ArgumentError::NoCommand => write!(f, "no command was given"),
             ArgumentError::NoInputs => write!(f, "no inputs were supplied"),
             ArgumentError::Invalid(ref op) => write!(f, "invalid argument operator: '{}'", op),
+            ArgumentError::InvalidOption(ref op) => write!(f, "invalid option: '{}'", op),
+            ArgumentError::NoValue(op) => write!(f, "no value was supplied to '{}'", op),
+            ArgumentError::InvalidValue(op, ref value) => {
+                write!(f, "invalid value supplied to '{}': '{}'", op, value)
+            }
+            ArgumentError::Requires(op, required) => {
+                write!(f, "'{}' requires '{}' to be supplied", op, required)
+            }
+            ArgumentError::Conflicts(op, other) => {
+                write!(f, "'{}' may not be supplied alongside '{}'", op, other)
+            }
         }
     }
 }
 
 impl Arguments {
-    pub fn new() -> Result<Arguments, ArgumentError> {
-        let mut args = args().skip(1);
-
-        // THe first argument should be the command.
-        let command = args.next().ok_or(ArgumentError::NoCommand)?;
-
-        // Check if any redirections happened, and if so, this will notify the program to
-        // obtain pipes from the source of the redirection directly.
-        match redirection::source() {
-            Some(RedirectionSource::Pipe) => {
-                return Ok(Arguments {
-                    command,
-                    args: ArgsSource::RedirPipe,
-                });
+    pub fn new() -> Result<Arguments, ArgumentError> { Arguments::from_args(args().skip(1)) }
+
+    /// Parses the given arguments, which exclude the name of the program.
+    fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Arguments, ArgumentError> {
+        let mut colsep = None;
+        let mut format = Format::default();
+        let mut mode = Mode::Shell;
+        let mut exit_policy = ExitPolicy::Count;
+        let mut halt = None;
+        let mut timeout = None;
+        let mut retries = 0;
+        let mut elsewhere = false;
+        let mut joblog = None;
+        let mut resume = None;
+        let mut progress = None;
+        let mut results = None;
+        let mut output_mode = OutputMode::Keep;
+        let mut tag = None;
+        let mut max_output = None;
+        let mut max_memory = Limit(64 << 20);
+        let mut max_pending = MAX_PENDING;
+
+        // Options are supplied before the command, which is the first non-option argument.
+        let command = loop {
+            let arg = args.next().ok_or(ArgumentError::NoCommand)?;
+            match arg.as_str() {
+                "--colsep" => colsep = Some(value(&mut args, "--colsep")?),
+                "--delimiter" => {
+                    let Delimiter(delimiter) = parse_value(&mut args, "--delimiter")?;
+                    format.delimiter = delimiter;
+                }
+                "--eta" => progress = progress.or(Some(progress::Mode::Eta)),
+                "--exec" => mode = Mode::Exec,
+                "--exit-policy" => exit_policy = parse_value(&mut args, "--exit-policy")?,
+                "--halt" => halt = Some(parse_value(&mut args, "--halt")?),
+                "--joblog" => joblog = Some(PathBuf::from(value(&mut args, "--joblog")?)),
+                "--max-memory" => max_memory = parse_value(&mut args, "--max-memory")?,
+                "--max-output" => max_output = Some(parse_value(&mut args, "--max-output")?),
+                "--max-pending" => {
+                    let value = value(&mut args, "--max-pending")?;
+                    max_pending = match value.parse::<usize>() {
+                        Ok(max_pending) if max_pending > 0 => max_pending,
+                        _ => return Err(ArgumentError::InvalidValue("--max-pending", value)),
+                    };
+                }
+                "--no-comments" => format.comments = false,
+                "--no-trim" => format.trim = false,
+                "-0" | "--null" => {
+                    format.delimiter = 0;
+                    format.trim = false;
+                    format.comments = false;
+                }
+                "--output-mode" => output_mode = parse_value(&mut args, "--output-mode")?,
+                "--progress" => progress = Some(progress::Mode::Nodes),
+                "--results" => results = Some(PathBuf::from(value(&mut args, "--results")?)),
+                "--resume" => resume = Some(Resume::Executed),
+                "--resume-failed" => resume = Some(Resume::Succeeded),
+                "--retries" => {
+                    // Each attempt of a job, counting from 1, must fit within a `u8`.
+                    let value = value(&mut args, "--retries")?;
+                    retries = match value.parse::<u8>() {
+                        Ok(retries) if retries < u8::MAX => retries,
+                        _ => return Err(ArgumentError::InvalidValue("--retries", value)),
+                    };
+                }
+                "--retry-elsewhere" => elsewhere = true,
+                "--tag" => tag = tag.or_else(|| Some(Tokens::template("{}"))),
+                "--tagstring" => tag = Some(Tokens::template(&value(&mut args, "--tagstring")?)),
+                "--timeout" => timeout = Some(parse_value(&mut args, "--timeout")?),
+                "--" => break args.next().ok_or(ArgumentError::NoCommand)?,
+                _ if arg.starts_with('-') => return Err(ArgumentError::InvalidOption(arg)),
+                _ => break arg,
             }
-            Some(RedirectionSource::File(path)) => {
-                return Ok(Arguments {
-                    command,
-                    args: ArgsSource::RedirFile(path),
-                });
+        };
+
+        match resume {
+            Some(Resume::Executed) if joblog.is_none() => {
+                return Err(ArgumentError::Requires("--resume", "--joblog"))
             }
-            None => (),
+            Some(Resume::Succeeded) if joblog.is_none() => {
+                return Err(ArgumentError::Requires("--resume-failed", "--joblog"))
+            }
+            _ => (),
         }
 
-        // Otherwise, we will attempt to parse arguments supplied to the command line.
-        let mut store = Vec::new();
-        // If the user specified permutated inputs, we will need to a place to store actions.
-        // The value indicates what types of inputs are being read. Files? Strings? Appends?
-        let mut action;
-
-        if let Some(arg) = args.next() {
-            action = match arg.as_str() {
-                ":" => parse(&mut store, &mut args, true),
-                "::" => parse(&mut store, &mut args, false),
-                _ => return Err(ArgumentError::Invalid(arg)),
-            };
-            loop {
-                action = match action {
-                    Action::ParseStrings => parse(&mut store, &mut args, true),
-                    Action::ParseFiles => parse(&mut store, &mut args, false),
-                    Action::Stop => break,
-                }
-            }
-            Ok(Arguments {
-                command,
-                args: ArgsSource::Cli(store),
-            })
-        } else {
-            Err(ArgumentError::NoInputs)
+        if output_mode == OutputMode::Line && results.is_some() {
+            return Err(ArgumentError::Conflicts("--output-mode line", "--results"));
         }
+
+        let args = parse_inputs(&mut args)?;
+        Ok(Arguments {
+            command,
+            args,
+            colsep,
+            format,
+            mode,
+            exit_policy,
+            halt,
+            timeout,
+            retries,
+            elsewhere,
+            joblog,
+            resume,
+            progress,
+            results,
+            output_mode,
+            tag,
+            max_output,
+            max_memory,
+            max_pending,
+        })
     }
 
     pub fn get_command<'a>(&'a self) -> &'a str { self.command.as_str() }
 }
 
+/// Obtains the value that must follow the given option.
+fn value<I: Iterator<Item = String>>(
+    args: &mut I,
+    option: &'static str,
+) -> Result<String, ArgumentError> {
+    args.next().ok_or(ArgumentError::NoValue(option))
+}
+
+/// Obtains the value that must follow the given option, and parses it.
+fn parse_value<I: Iterator<Item = String>, T: FromStr>(
+    args: &mut I,
+    option: &'static str,
+) -> Result<T, ArgumentError> {
+    let value = value(args, option)?;
+    value.parse::<T>().map_err(|_| ArgumentError::InvalidValue(option, value))
+}
+
+fn parse_inputs<I: Iterator<Item = String>>(args: &mut I) -> Result<ArgsSource, ArgumentError> {
+    // Check if any redirections happened, and if so, this will notify the program to
+    // obtain pipes from the source of the redirection directly.
+    match redirection::source() {
+        Some(RedirectionSource::Pipe) => return Ok(ArgsSource::RedirPipe),
+        Some(RedirectionSource::File(path)) => return Ok(ArgsSource::RedirFile(path)),
+        None => (),
+    }
+
+    // Otherwise, we will attempt to parse arguments supplied to the command line.
+    let mut store = Vec::new();
+    // If the user specified permutated inputs, we will need to a place to store actions.
+    // The value indicates what types of inputs are being read. Files? Strings? Appends?
+    let mut action;
+
+    if let Some(arg) = args.next() {
+        action = match arg.as_str() {
+            ":" => parse(&mut store, args, true),
+            "::" => parse(&mut store, args, false),
+            _ => return Err(ArgumentError::Invalid(arg)),
+        };
+        loop {
+            action = match action {
+                Action::ParseStrings => parse(&mut store, args, true),
+                Action::ParseFiles => parse(&mut store, args, false),
+                Action::Stop => break,
+            }
+        }
+        Ok(ArgsSource::Cli(store))
+    } else {
+        Err(ArgumentError::NoInputs)
+    }
+}
+
 fn parse<I: Iterator<Item = String>>(
     vec: &mut Vec<ArgUnit>,
     iter: &mut I,
@@ -125,3 +298,69 @@ fn parse<I: Iterator<Item = String>>(
     vec.push(if is_string { ArgUnit::Strings(args) } else { ArgUnit::Files(args) });
     Action::Stop
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_value, ArgumentError, Arguments};
+    use joblog::Resume;
+
+    fn arguments(args: &[&str]) -> Result<Arguments, ArgumentError> {
+        Arguments::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn values() {
+        let retries = |args: &[&str]| {
+            parse_value::<_, u8>(&mut args.iter().map(|arg| arg.to_string()), "--retries")
+        };
+        let cases: &[(&[&str], Result<u8, ArgumentError>)] = &[
+            (&["3"], Ok(3)),
+            (&["3", "4"], Ok(3)),
+            (&["-1"], Err(ArgumentError::InvalidValue("--retries", "-1".into()))),
+            (&["256"], Err(ArgumentError::InvalidValue("--retries", "256".into()))),
+            (&[], Err(ArgumentError::NoValue("--retries"))),
+        ];
+        for &(args, ref expected) in cases {
+            assert_eq!(retries(args), *expected, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn conflicts() {
+        let cases: &[(&[&str], ArgumentError)] = &[
+            (&[], ArgumentError::NoCommand),
+            (&["--"], ArgumentError::NoCommand),
+            (&["--unknown", "echo"], ArgumentError::InvalidOption("--unknown".into())),
+            (&["--halt"], ArgumentError::NoValue("--halt")),
+            (&["--halt", "fail", "echo"], ArgumentError::InvalidValue("--halt", "fail".into())),
+            (
+                &["--halt", "later,fail=1", "echo"],
+                ArgumentError::InvalidValue("--halt", "later,fail=1".into()),
+            ),
+            (&["--resume", "echo"], ArgumentError::Requires("--resume", "--joblog")),
+            (
+                &["--resume-failed", "echo"],
+                ArgumentError::Requires("--resume-failed", "--joblog"),
+            ),
+            (
+                &["--output-mode", "line", "--results", "out", "echo"],
+                ArgumentError::Conflicts("--output-mode line", "--results"),
+            ),
+            (&["--retries", "255", "echo"], ArgumentError::InvalidValue("--retries", "255".into())),
+            (
+                &["--max-pending", "0", "echo"],
+                ArgumentError::InvalidValue("--max-pending", "0".into()),
+            ),
+        ];
+        for &(args, ref expected) in cases {
+            assert_eq!(arguments(args).unwrap_err(), *expected, "{:?}", args);
+        }
+
+        let args = ["--halt", "now,fail=1", "--retries", "254", "--joblog", "log", "--resume"];
+        let arguments = arguments(&[&args[..], &["echo", ":", "a"]].concat()).unwrap();
+        assert_eq!(arguments.get_command(), "echo");
+        assert_eq!(arguments.halt, "now,fail=1".parse().ok());
+        assert_eq!(arguments.retries, 254);
+        assert_eq!(arguments.resume, Some(Resume::Executed));
+    }
+}
@@ -4,6 +4,7 @@ use native_tls::Certificate;
 use std::fs::File;
 use std::io::Read;
 use std::process::exit;
+use summary::ERROR_STATUS;
 
 pub fn get(domain: &str) -> Certificate {
     let cert = [domain, ".der"].concat();
@@ -18,15 +19,15 @@ pub fn get(domain: &str) -> Certificate {
         Ok(Ok(Ok(cert))) => cert,
         Ok(Ok(Err(why))) => {
             eprintln!("concurr [CRITICAL]: error parsing '{}' in cert path: {}", cert, why);
-            exit(1);
+            exit(ERROR_STATUS);
         }
         Ok(Err(why)) => {
             eprintln!("concurr [CRITICAL]: error reading '{}' in cert path: {}", cert, why);
-            exit(1);
+            exit(ERROR_STATUS);
         }
         Err(why) => {
             eprintln!("concurr [CRITICAL]: invalid app dir path: {}", why);
-            exit(1);
+            exit(ERROR_STATUS);
         }
     }
 }
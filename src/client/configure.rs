@@ -21,6 +21,7 @@ struct RawConfig {
     localhost: Option<bool>,
     outputs:   Option<bool>,
     verbose:   Option<bool>,
+    window:    Option<usize>,
 }
 
 impl RawConfig {
@@ -32,7 +33,11 @@ impl RawConfig {
         let mut flags = if self.outputs.unwrap_or(false) { OUTPUTS } else { 0 };
         flags |= if self.verbose.unwrap_or(false) { VERBOSE } else { 0 };
         flags |= if self.localhost.unwrap_or(true) { LOCHOST } else { 0 };
-        Ok(Config { nodes, flags })
+        Ok(Config {
+            nodes,
+            flags,
+            window: self.window,
+        })
     }
 }
 
@@ -43,6 +48,8 @@ pub const LOCHOST: u8 = 4;
 pub struct Config {
     pub nodes: Vec<(SocketAddr, String)>,
     pub flags: u8,
+    /// The number of inputs that may be in flight on each node at a time.
+    pub window: Option<usize>,
 }
 
 impl Config {
@@ -71,6 +78,9 @@ localhost = true
 outputs = true
 # Whether additional information about jobs should be printed.
 verbose = false
+# How many inputs may be submitted to each node before waiting for results.
+# Defaults to the number of cores on each node, and is at most 32.
+# window = 8
 "#;
 
 fn read_file(path: &Path, buffer: &mut String) -> io::Result<()> {
@@ -1,4 +1,6 @@
 use certificate;
+use concurr::{frame, Limit, Mode, Timeout};
+use concurr::protocol::{self, Hello};
 use native_tls::{Certificate, TlsConnector, TlsStream};
 use std::fmt::{self, Display, Formatter};
 use std::io::{self, BufRead, BufReader, Read, Write};
@@ -10,6 +12,8 @@ use std::time::Duration;
 #[derive(Debug)]
 pub enum ConnectionError {
     IO(io::Error),
+    /// The node does not support a feature that is required by the client.
+    Unsupported(SocketAddr, &'static str),
 }
 
 impl From<io::Error> for ConnectionError {
@@ -20,6 +24,11 @@ impl Display for ConnectionError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             ConnectionError::IO(ref err) => write!(f, "I/O error: {}", err),
+            ConnectionError::Unsupported(address, feature) => write!(
+                f,
+                "{} does not support {}; concurr-jobsd must be upgraded on that node",
+                address, feature
+            ),
         }
     }
 }
@@ -30,10 +39,17 @@ pub struct Connection {
     pub address:    SocketAddr,
     pub command:    usize,
     pub cores:      usize,
+    /// The protocol version and features that the node announced.
+    pub hello:      Hello,
+    /// Whether a command was created on the node, which will be deleted when dropped.
+    created:        bool,
 }
 
 impl Drop for Connection {
     fn drop(&mut self) {
+        if !self.created {
+            return;
+        }
         let result = self.connection
             .write_all(["del ", &(self.command).to_string(), "\r\n"].concat().as_bytes());
         if let Err(_) = result {
@@ -47,6 +63,25 @@ impl Drop for Connection {
 impl Connection {
     pub fn new(address: SocketAddr, domain: String) -> Result<Connection, ConnectionError> {
         let mut connection = attempt_connection(address, &domain, certificate::get(&domain))?;
+
+        // Nodes which predate the `hello` exchange will close the connection upon receiving
+        // it, in which case the first version of the protocol will be spoken on a new one.
+        let hello = match exchange_hello(&mut connection)? {
+            Some(hello) => hello,
+            None => {
+                connection = attempt_connection(address, &domain, certificate::get(&domain))?;
+                Hello::legacy()
+            }
+        };
+
+        if hello.version < protocol::VERSION {
+            eprintln!(
+                "concurr [WARN]: {} speaks version {} of the protocol; some features will be \
+                 unavailable until concurr-jobsd is upgraded on that node",
+                address, hello.version
+            );
+        }
+
         let cores = get_cores(&mut connection)?;
 
         Ok(Connection {
@@ -55,20 +90,47 @@ impl Connection {
             address,
             command: 0,
             cores,
+            hello,
+            created: false,
         })
     }
 
-    pub fn send_command(&mut self, command: &str) -> io::Result<usize> {
+    pub fn send_command(
+        &mut self,
+        command: &str,
+        mode: Mode,
+        timeout: Option<Timeout>,
+        limit: Option<Limit>,
+    ) -> io::Result<usize> {
         let mut string = String::new();
-        let instruction = ["com ", command, "\r\n"].concat();
+        let mut options = String::new();
+        if mode == Mode::Exec {
+            options.push_str("+exec ");
+        }
+        if let Some(timeout) = timeout {
+            options.push_str(&format!("+timeout={} ", timeout));
+        }
+        if let Some(limit) = limit {
+            options.push_str(&format!("+max-output={} ", limit));
+        }
+        let instruction = ["com ", &options, command, "\r\n"].concat();
         attempt_write(&mut self.connection, instruction)?;
         BufReader::new(&mut self.connection).read_line(&mut string)?;
         let id = string[..string.len() - 1]
             .parse::<usize>()
             .map_err(|_| io::Error::new(io::ErrorKind::Other, "ID is NaN"))?;
         self.command = id;
+        self.created = true;
         Ok(id)
     }
+
+    /// Deletes the command from the node, killing the jobs that are running on it.
+    pub fn kill(&mut self) -> io::Result<()> {
+        let instruction = ["del +kill ", &self.command.to_string(), "\r\n"].concat();
+        attempt_write(&mut self.connection, instruction)?;
+        self.created = false;
+        Ok(())
+    }
 }
 
 pub fn attempt_connection<DOMAIN: AsRef<str>>(
@@ -140,6 +202,43 @@ pub fn attempt_write<STREAM: Write, INSTRUCTION: AsRef<[u8]>>(
     Ok(())
 }
 
+/// Announces the protocol version and features of the client, and returns those of the node.
+/// If the node did not respond with a `hello`, it only supports the first version of the
+/// protocol, and `None` is returned.
+fn exchange_hello<STREAM: Read + Write>(stream: &mut STREAM) -> io::Result<Option<Hello>> {
+    attempt_write(stream, [Hello::new().to_string().as_str(), "\r\n"].concat())?;
+    let mut string = String::new();
+    match BufReader::new(stream).read_line(&mut string) {
+        Ok(_) => Ok(Hello::parse(&string)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Requests the node to switch the connection over to length-prefixed frames, so that inputs
+/// and outputs may contain arbitrary bytes.
+pub fn upgrade<STREAM: Read + Write>(stream: &mut STREAM) -> io::Result<()> {
+    attempt_write(stream, b"set framed\r\n")?;
+    let mut string = String::new();
+    BufReader::new(stream).read_line(&mut string)?;
+    if string == "OK framed\n" {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, "node does not support framing"))
+    }
+}
+
+/// Requests the node to stream the outputs of the jobs that are submitted on the connection
+/// while they run. The connection must already have been upgraded to frames.
+pub fn enable_streaming<STREAM: Read + Write>(stream: &mut STREAM) -> io::Result<()> {
+    let mut instruction = Vec::new();
+    frame::encode(&[b"set streaming"], &mut instruction)?;
+    attempt_write(stream, instruction)?;
+    match frame::read(stream)?.first() {
+        Some(header) if header == b"OK streaming" => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::Other, "node does not support streaming")),
+    }
+}
+
 fn get_cores<STREAM: Read + Write>(stream: &mut STREAM) -> io::Result<usize> {
     attempt_write(stream, b"get cores\r\n")?;
     let mut string = String::new();
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+use summary::Summary;
+
+/// Percentages are not considered until at least this many jobs have completed.
+const MIN_JOBS: usize = 3;
+
+/// Determines whether running jobs are killed once a run has been halted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum When {
+    /// Running jobs are killed.
+    Now,
+    /// Running jobs are allowed to complete.
+    Soon,
+}
+
+/// The outcome of the jobs that are counted towards the threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    Fail,
+    Success,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    /// A number of jobs.
+    Jobs(usize),
+    /// A percentage of the jobs that have completed.
+    Percent(usize),
+}
+
+/// Stops dispatching new inputs once a number or a percentage of jobs have failed, or have
+/// succeeded, such as `now,fail=1`, `soon,success=3`, or `fail=10%`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HaltPolicy {
+    pub when:      When,
+    pub trigger:   Trigger,
+    pub threshold: Threshold,
+}
+
+impl HaltPolicy {
+    /// Whether the jobs that have completed so far meet the threshold of the policy.
+    pub fn check(&self, summary: &Summary) -> bool {
+        let jobs = match self.trigger {
+            Trigger::Fail => summary.failures(),
+            Trigger::Success => summary.succeeded,
+        };
+
+        match self.threshold {
+            Threshold::Jobs(threshold) => jobs >= threshold,
+            Threshold::Percent(percent) => {
+                summary.total() >= MIN_JOBS && jobs * 100 >= percent * summary.total()
+            }
+        }
+    }
+}
+
+impl FromStr for HaltPolicy {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<HaltPolicy, ()> {
+        // If it is not specified, running jobs will be allowed to complete.
+        let (when, condition) = match input.find(',') {
+            Some(pos) => (&input[..pos], &input[pos + 1..]),
+            None => ("soon", input),
+        };
+
+        let when = match when {
+            "now" => When::Now,
+            "soon" => When::Soon,
+            _ => return Err(()),
+        };
+
+        let pos = condition.find('=').ok_or(())?;
+        let trigger = match &condition[..pos] {
+            "fail" => Trigger::Fail,
+            "success" => Trigger::Success,
+            _ => return Err(()),
+        };
+
+        let value = &condition[pos + 1..];
+        let threshold = if value.ends_with('%') {
+            match value[..value.len() - 1].parse::<usize>() {
+                Ok(percent) if percent > 0 && percent <= 100 => Threshold::Percent(percent),
+                _ => return Err(()),
+            }
+        } else {
+            match value.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Threshold::Jobs(jobs),
+                _ => return Err(()),
+            }
+        };
+
+        Ok(HaltPolicy {
+            when,
+            trigger,
+            threshold,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HaltPolicy, Threshold, Trigger, When};
+    use concurr::Status;
+    use summary::Summary;
+
+    #[test]
+    fn policies() {
+        let policy = "now,fail=1".parse::<HaltPolicy>().unwrap();
+        assert_eq!(
+            policy,
+            HaltPolicy {
+                when:      When::Now,
+                trigger:   Trigger::Fail,
+                threshold: Threshold::Jobs(1),
+            }
+        );
+
+        let percent = "fail=50%".parse::<HaltPolicy>().unwrap();
+        assert_eq!(percent.when, When::Soon);
+        assert_eq!(percent.threshold, Threshold::Percent(50));
+
+        assert!("later,fail=1".parse::<HaltPolicy>().is_err());
+        assert!("now,fail=0".parse::<HaltPolicy>().is_err());
+        assert!("now,success=101%".parse::<HaltPolicy>().is_err());
+
+        let mut summary = Summary::default();
+        summary.record_status(Status::Exited(2));
+        assert!(policy.check(&summary));
+        // Percentages require a minimum number of jobs to have completed.
+        assert!(!percent.check(&summary));
+        summary.record_status(Status::Exited(0));
+        summary.record_status(Status::Exited(0));
+        assert!(!percent.check(&summary));
+        summary.record_unexecuted();
+        assert!(percent.check(&summary));
+        assert!(!"success=3".parse::<HaltPolicy>().unwrap().check(&summary));
+    }
+}
@@ -1,19 +1,225 @@
-use concurr::InsertJob;
-use std::collections::VecDeque;
+use concurr::{InsertJob, Queue, Status};
+use progress::Progress;
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
 
+/// Identifies the client, when it is also acting as a node. Remote nodes are identified by their
+/// position within the configuration, starting from `1`.
+pub const LOCAL: usize = 0;
+
+/// The number of inputs that may be waiting to be processed at once, by default.
+pub const MAX_PENDING: usize = 1024;
+
+/// An input that is waiting to be dispatched to a slot.
+pub struct Pending {
+    pub id:      usize,
+    pub input:   Vec<String>,
+    /// The number of times that the job will have been executed, once it has been dispatched.
+    pub attempt: u8,
+    /// The number of times that the input could not be transmitted to a node.
+    pub tries:   u8,
+    /// The node that the previous attempt failed on, which should be avoided if possible.
+    pub avoid:   Option<usize>,
+}
+
+/// Each input is stored alongside the number of attempts that have been made to process it.
+///
+/// # Retries
+///
+/// Jobs which did not succeed are placed back onto the queue until they have been attempted
+/// `retries + 1` times. As local and remote slots both take their inputs from here, and place
+/// their results into the outputs buffer, the same machinery applies to both.
+///
+/// Inputs which are placed back onto the queue are not held to its bound, as the slot placing
+/// them must never park on the queue that it takes its inputs from. Each of them was taken from
+/// the queue, so it never holds more than the bound and the jobs that were in flight.
 pub struct Inputs {
-    pub inputs: Mutex<VecDeque<(usize, String)>>,
+    pub inputs:  Queue<Pending>,
+    retries:     u8,
+    /// Whether a job that failed on a node should preferably be retried on another.
+    elsewhere:   bool,
+    /// The input, and attempt number, of each job that has been dispatched, so that it may be
+    /// placed back onto the queue if it fails, and reported alongside its outputs.
+    dispatched:  Mutex<HashMap<usize, (Vec<String>, u8)>>,
+    /// Jobs which completed in a previous run, and will not be queued.
+    skip:        HashSet<usize>,
+    /// The number of inputs that may be waiting to be processed at once. Sources which supply
+    /// more park until the slots have taken inputs from the queue, so that large inputs are not
+    /// read into memory ahead of the jobs.
+    max_pending: usize,
+    /// The total number of jobs, and how many of them are skipped, once it has been counted.
+    skipped:     Mutex<Option<(usize, usize)>>,
+    /// Counts the jobs that each node is running, and has completed.
+    pub progress: Progress,
+}
+
+impl Inputs {
+    pub fn new(
+        retries: u8,
+        elsewhere: bool,
+        skip: HashSet<usize>,
+        nodes: usize,
+        max_pending: usize,
+    ) -> Inputs {
+        Inputs {
+            inputs: Queue::new(),
+            retries,
+            elsewhere,
+            dispatched: Mutex::new(HashMap::new()),
+            skip,
+            skipped: Mutex::new(None),
+            max_pending,
+            progress: Progress::new(nodes),
+        }
+    }
+
+    /// Whether the job completed in a previous run.
+    pub fn skips(&self, id: usize) -> bool { self.skip.contains(&id) }
+
+    /// The number of jobs, out of the given total, that completed in a previous run. As the
+    /// total is only given once every input has been read, it is counted once and then cached.
+    pub fn skipped(&self, total: usize) -> usize {
+        let mut skipped = self.skipped.lock().unwrap();
+        match *skipped {
+            Some((counted, count)) if counted == total => count,
+            _ => {
+                let count = self.skip.iter().filter(|&&id| id < total).count();
+                *skipped = Some((total, count));
+                count
+            }
+        }
+    }
+
+    /// The input of a job that has been dispatched, and has yet to finish.
+    pub fn input(&self, id: usize) -> Vec<String> {
+        let dispatched = self.dispatched.lock().unwrap();
+        dispatched.get(&id).map_or_else(Vec::new, |entry| entry.0.clone())
+    }
+
+    /// Parks until the next input that may be processed by the given node is available.
+    pub fn next(&self, node: usize) -> Option<Pending> {
+        loop {
+            let pending = self.inputs.pop()?;
+            if let Some(pending) = self.dispatch(pending, node) {
+                return Some(pending);
+            }
+        }
+    }
+
+    /// Takes the next input if one is available, without parking.
+    pub fn try_next(&self, node: usize) -> Option<Pending> {
+        loop {
+            let pending = self.inputs.try_pop()?;
+            if let Some(pending) = self.dispatch(pending, node) {
+                return Some(pending);
+            }
+        }
+    }
+
+    /// A job which failed on the given node is deferred once, by placing it at the back of
+    /// the queue, so that another node may take it. If no other node takes it in the meantime,
+    /// it will be processed by whichever node takes it next.
+    fn dispatch(&self, mut pending: Pending, node: usize) -> Option<Pending> {
+        if pending.avoid.take() == Some(node) {
+            self.inputs.push_back(pending);
+            return None;
+        }
+
+        let entry = (pending.input.clone(), pending.attempt);
+        self.dispatched.lock().unwrap().insert(pending.id, entry);
+        self.progress.started(node);
+        Some(pending)
+    }
+
+    /// Places an input that could not be transmitted to the node at the front of the queue, so
+    /// that the next available slot will reattempt it.
+    pub fn retry(&self, mut pending: Pending, node: usize) {
+        self.progress.retried(node);
+        pending.tries += 1;
+        self.inputs.push_front(pending);
+    }
+
+    /// Called once the job has completed on the given node, or could not be executed at all.
+    /// If the job failed and has attempts remaining, it is placed back onto the queue, and
+    /// `None` is returned. Otherwise, the attempt number of the final attempt is returned,
+    /// along with the input of the job.
+    pub fn finish(
+        &self,
+        id: usize,
+        status: Option<Status>,
+        node: usize,
+    ) -> Option<(u8, Vec<String>)> {
+        let (input, attempt) = match self.dispatched.lock().unwrap().remove(&id) {
+            Some(entry) => entry,
+            None => return Some((1, Vec::new())),
+        };
+
+        match status {
+            Some(status) if status != Status::Exited(0) && attempt <= self.retries => {
+                self.inputs.push_front(Pending {
+                    id,
+                    input,
+                    attempt: attempt + 1,
+                    tries: 0,
+                    avoid: if self.elsewhere { Some(node) } else { None },
+                });
+                None
+            }
+            _ => Some((attempt, input)),
+        }
+    }
+
+    /// Wakes every slot that is parked on the queue, signaling them to exit.
+    pub fn close(&self) { self.inputs.close(); }
 }
 
 impl InsertJob for Inputs {
-    fn get_job(&self) -> Option<(usize, String)> {
-        let mut lock = self.inputs.lock().unwrap();
-        lock.pop_front()
+    fn get_job(&self) -> Option<(usize, Vec<String>)> {
+        self.next(LOCAL).map(|pending| (pending.id, pending.input))
+    }
+
+    /// Parks while too many inputs are waiting to be processed.
+    fn insert_job(&self, id: usize, input: Vec<String>) {
+        if self.skips(id) {
+            return;
+        }
+        let pending = Pending {
+            id,
+            input,
+            attempt: 1,
+            tries: 0,
+            avoid: None,
+        };
+        self.inputs.push_bounded(pending, self.max_pending);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inputs, LOCAL, MAX_PENDING};
+    use concurr::{InsertJob, Status};
+    use std::collections::HashSet;
+
+    #[test]
+    fn retries() {
+        let inputs = Inputs::new(1, true, HashSet::new(), 2, MAX_PENDING);
+        inputs.insert_job(0, vec!["a".into()]);
+        inputs.insert_job(1, vec!["b".into()]);
+
+        let first = inputs.next(LOCAL).unwrap();
+        assert_eq!((first.id, first.attempt), (0, 1));
+        assert_eq!(inputs.finish(0, Some(Status::Exited(1)), LOCAL), None);
+
+        // The failed job is taken first, unless it is taken by the node that it failed on.
+        let retried = inputs.try_next(1).unwrap();
+        assert_eq!((retried.id, retried.attempt), (0, 2));
+        assert_eq!(inputs.finish(0, Some(Status::Exited(1)), 1), Some((2, vec!["a".into()])));
 
-    fn insert_job(&self, id: usize, job: String) {
-        let mut lock = self.inputs.lock().unwrap();
-        lock.push_back((id, job));
+        let second = inputs.next(LOCAL).unwrap();
+        assert_eq!(inputs.finish(second.id, Some(Status::TimedOut), LOCAL), None);
+        let deferred = inputs.next(LOCAL).unwrap();
+        assert_eq!((deferred.id, deferred.attempt), (1, 2));
+        assert_eq!(inputs.finish(1, None, LOCAL), Some((2, vec!["b".into()])));
+        assert!(inputs.try_next(LOCAL).is_none());
     }
 }
@@ -0,0 +1,209 @@
+use concurr::Seconds;
+use outputs::Details;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The columns of the job log, which are separated by tabs.
+const HEADER: &str = "Seq\tHost\tStarttime\tJobRuntime\tStdout\tStderr\tExitval\tSignal\tCommand";
+
+/// The exit status of jobs which could not be executed, which no job that was executed has.
+const FAILED: &str = "-1";
+
+/// Determines which of the jobs in the log of a previous run will be skipped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    /// Every job that was executed, regardless of its exit status.
+    Executed,
+    /// Only the jobs that succeeded, so that the jobs which failed will be executed again.
+    Succeeded,
+}
+
+/// Records the final attempt of every job that was executed, one line per job, in the order
+/// that their outputs were written. The host is `:` for jobs that were executed by the client.
+/// Times are written in seconds, and the start time is relative to the UNIX epoch. Tabs,
+/// newlines, and backslashes within commands are escaped, so that each line keeps its columns.
+/// Jobs which could not be executed are recorded with an exit status of `-1`.
+pub struct JobLog {
+    file: BufWriter<File>,
+}
+
+impl JobLog {
+    /// When resuming, lines are appended to the log of the previous run, if there is one.
+    pub fn create(path: &Path, resume: bool) -> io::Result<JobLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        let mut file = BufWriter::new(file);
+        if empty {
+            writeln!(file, "{}", HEADER)?;
+        }
+        Ok(JobLog { file })
+    }
+
+    /// Each line is flushed as it is written, so that the log remains complete if the client
+    /// is interrupted.
+    pub fn record(&mut self, id: usize, host: &str, details: &Details, bytes: (u64, u64)) {
+        let line = line(id, host, details, bytes);
+        self.write(&line);
+    }
+
+    /// Records a job that could not be executed, or sent to the host, at the current time.
+    pub fn record_failed(&mut self, id: usize, host: &str) {
+        let line = failed_line(id, host, SystemTime::now());
+        self.write(&line);
+    }
+
+    fn write(&mut self, line: &str) {
+        let result = writeln!(self.file, "{}", line).and_then(|_| self.file.flush());
+        if let Err(why) = result {
+            eprintln!("concurr [CRITICAL]: unable to write to the job log: {}", why);
+        }
+    }
+}
+
+/// Reads the log of a previous run, returning the IDs of the jobs that will be skipped. If the
+/// log does not exist, no jobs will be skipped.
+pub fn completed(path: &Path, resume: Resume) -> io::Result<HashSet<usize>> {
+    match File::open(path) {
+        Ok(file) => read_completed(BufReader::new(file), resume),
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(why) => Err(why),
+    }
+}
+
+/// Only the IDs and exit statuses are read, so the escaped commands need not be unescaped.
+fn read_completed<R: BufRead>(reader: R, resume: Resume) -> io::Result<HashSet<usize>> {
+    let mut completed = HashSet::new();
+    for line in reader.lines().skip(1) {
+        let line = line?;
+        let mut columns = line.split('\t');
+        // Lines which were only partially written, such as when the client was killed, are
+        // considered to be incomplete.
+        let id = match columns.next().map(str::parse::<usize>) {
+            Some(Ok(id)) => id,
+            _ => continue,
+        };
+        match columns.nth(5) {
+            Some("0") => (),
+            Some(FAILED) => continue,
+            Some(_) if resume == Resume::Executed => (),
+            _ => continue,
+        }
+        completed.insert(id);
+    }
+    Ok(completed)
+}
+
+fn line(id: usize, host: &str, details: &Details, (stdout, stderr): (u64, u64)) -> String {
+    let execution = &details.execution;
+    let start = execution.timing.start.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        id,
+        host,
+        Seconds(start),
+        Seconds(execution.timing.runtime),
+        stdout,
+        stderr,
+        execution.status,
+        execution.timing.signal,
+        escape(&execution.command)
+    )
+}
+
+/// The job was never executed, so its runtime, outputs, and command are empty.
+fn failed_line(id: usize, host: &str, start: SystemTime) -> String {
+    let start = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let runtime = Seconds(Duration::default());
+    format!("{}\t{}\t{}\t{}\t0\t0\t{}\t0\t", id, host, Seconds(start), runtime, FAILED)
+}
+
+/// Escapes the characters which would otherwise split a column or a line.
+fn escape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for character in input.chars() {
+        match character {
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\\' => output.push_str("\\\\"),
+            _ => output.push(character),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{failed_line, line, read_completed, Resume, HEADER};
+    use concurr::{Execution, Status, Timing};
+    use outputs::Details;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn lines() {
+        let details = Details {
+            execution: Execution {
+                status:  Status::TimedOut,
+                timing:  Timing {
+                    start:   UNIX_EPOCH + Duration::from_millis(1_508_000_000_250),
+                    runtime: Duration::from_millis(30_001),
+                    signal:  15,
+                },
+                command: "sleep 60".into(),
+            },
+            attempt:   1,
+            node:      0,
+            input:     vec!["60".into()],
+        };
+        assert_eq!(
+            line(3, ":", &details, (12, 0)),
+            "3\t:\t1508000000.250\t30.001\t12\t0\ttimeout\t15\tsleep 60"
+        );
+
+        let mut details = details;
+        details.execution.command = "printf 'a\tb\\n'\necho 1".into();
+        assert_eq!(
+            line(4, ":", &details, (0, 0)),
+            "4\t:\t1508000000.250\t30.001\t0\t0\ttimeout\t15\tprintf 'a\\tb\\\\n'\\necho 1"
+        );
+
+        let start = UNIX_EPOCH + Duration::from_millis(1_508_000_000_250);
+        assert_eq!(failed_line(5, "node", start), "5\tnode\t1508000000.250\t0.000\t0\t0\t-1\t0\t");
+    }
+
+    #[test]
+    fn resume() {
+        let log = [
+            HEADER,
+            "0\t:\t1.000\t0.001\t0\t0\t0\t0\ttrue",
+            "1\t:\t1.000\t0.001\t0\t0\t1\t0\tfalse",
+            "2\t:\t1.000\t30.001\t0\t0\ttimeout\t15\tsleep 60",
+            // Job 1 succeeded when it was resumed.
+            "1\t:\t2.000\t0.001\t0\t0\t0\t0\tfalse",
+            "3\t:\t2.0",
+            // Job 4 could not be executed.
+            "4\tnode\t2.000\t0.000\t0\t0\t-1\t0\t",
+        ].join("\n");
+
+        let mut executed = read_completed(log.as_bytes(), Resume::Executed)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        executed.sort();
+        assert_eq!(executed, vec![0, 1, 2]);
+
+        let mut succeeded = read_completed(log.as_bytes(), Resume::Succeeded)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        succeeded.sort();
+        assert_eq!(succeeded, vec![0, 1]);
+    }
+}
@@ -1,5 +1,4 @@
 extern crate app_dirs;
-extern crate chashmap;
 extern crate concurr;
 extern crate libc;
 extern crate native_tls;
@@ -13,29 +12,42 @@ extern crate toml;
 mod args;
 mod certificate;
 mod configure;
+mod halt;
 mod connection;
 mod inputs;
+mod joblog;
 mod outputs;
 mod nodes;
+mod permutate;
+mod progress;
 mod redirection;
+mod report;
+mod results;
 mod slot;
 mod source;
+mod summary;
+mod tag;
 
 use self::inputs::Inputs;
-use self::outputs::{Output, Outputs};
+use self::outputs::{Budget, Lines, OutputMode, Outputs, Spill};
 use args::{ArgUnit, ArgsSource, Arguments};
-use chashmap::CHashMap;
-use concurr::{slot_event, InsertJob, Tokens};
+use concurr::{protocol, slot_event, Deadline, Mailbox, Running, Tokens};
 use configure::Config;
-use slot::Slot;
-use std::collections::VecDeque;
-use std::io::{self, Write};
+use halt::When;
+use joblog::JobLog;
+use progress::Screen;
+use report::Reporter;
+use results::Results;
+use slot::{Slot, MAX_WINDOW};
+use std::collections::HashSet;
 use std::path::Path;
 use std::process::exit;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 use std::time::Instant;
+use summary::Summary;
+use tag::Tag;
 
 fn main() {
     // Read the configuration file to get a list of nodes to connect to.
@@ -43,7 +55,7 @@ fn main() {
         Ok(config) => config,
         Err(why) => {
             eprintln!("concurr [CRITICAL]: {}", why);
-            exit(1);
+            exit(summary::ERROR_STATUS);
         }
     };
 
@@ -52,38 +64,88 @@ fn main() {
         Ok(arguments) => arguments,
         Err(why) => {
             eprintln!("concurr [CRITICAL]: {}", why);
-            exit(1);
+            exit(summary::ERROR_STATUS);
         }
     };
 
     // Collect a vector of nodes that we will send inputs to, and initialize them with a command.
-    let nodes = match nodes::get(config.nodes.into_iter(), arguments.get_command()) {
+    let command = arguments.get_command();
+    let nodes = nodes::get(
+        config.nodes.into_iter(),
+        command,
+        arguments.mode,
+        arguments.timeout,
+        arguments.max_output,
+    );
+    let mut nodes = match nodes {
         Ok(nodes) => nodes,
         Err(why) => {
             eprintln!("concurr [CRITICAL]: connection error: {}", why);
-            exit(1);
+            exit(summary::ERROR_STATUS);
         }
     };
 
+    // When resuming, the jobs that completed in the previous run are skipped. Jobs are numbered
+    // in the order that their inputs are read, so the remaining jobs keep their IDs.
+    let skip = match (arguments.joblog.as_ref(), arguments.resume) {
+        (Some(path), Some(resume)) => match joblog::completed(path, resume) {
+            Ok(skip) => skip,
+            Err(why) => {
+                eprintln!("concurr [CRITICAL]: unable to read {:?}: {}", path, why);
+                exit(summary::ERROR_STATUS);
+            }
+        },
+        _ => HashSet::new(),
+    };
+
+    if !skip.is_empty() && config.flags & configure::VERBOSE != 0 {
+        eprintln!("concurr [INFO]: skipping {} jobs from the previous run", skip.len());
+    }
+
+    // Jobs executed by the client are identified by `:`, and those of each node by its address.
+    let hosts = Some(":".to_owned())
+        .into_iter()
+        .chain(nodes.iter().map(|node| node.address.to_string()))
+        .collect::<Vec<String>>();
+
+    // Where the progress is drawn, if it is displayed.
+    let screen = arguments.progress.map(|_| Arc::new(Screen::new()));
+    let tag = arguments.tag.clone().map(|template| Tag::new(template, hosts.clone()));
+
     // Input and output queues that will be concurrently accessed across threads.
     let slot_id = Arc::new(AtomicUsize::new(0));
-    let inputs = Arc::new(Inputs {
-        inputs: Mutex::new(VecDeque::new()),
-    });
+    let inputs = Inputs::new(
+        arguments.retries,
+        arguments.elsewhere,
+        skip,
+        hosts.len(),
+        arguments.max_pending,
+    );
+    let inputs = Arc::new(inputs);
     let outputs = Arc::new(Outputs {
-        outputs: CHashMap::new(),
+        outputs: Mailbox::new(),
+        inputs:  inputs.clone(),
+        lines:   match arguments.output_mode {
+            OutputMode::Line => {
+                Some(Lines::new(screen.clone(), tag.clone(), arguments.max_output))
+            }
+            _ => None,
+        },
+        limit:   arguments.max_output,
+        budget:  Budget::new(arguments.max_memory),
+        spill:   Spill::default(),
     });
-    let errors = Arc::new(Mutex::new(VecDeque::new()));
-    let failed = Arc::new(CHashMap::new());
-    let kill = Arc::new(AtomicBool::new(false));
 
     // Useful for knowing when to exit the program
     let mut handles = Vec::new();
+    // Useful for killing the jobs that are running on the client, when halting.
+    let running = Arc::new(Running::new());
 
     // If enabled, the client will also act as a node.
     if config.flags & configure::LOCHOST != 0 {
-        let command = Tokens::new(arguments.get_command());
+        let command = Tokens::with_mode(arguments.get_command(), arguments.mode);
         let parked = Arc::new(AtomicUsize::new(0));
+        let deadline = arguments.timeout.map(|timeout| Arc::new(Deadline::new(timeout)));
         let cores = num_cpus::get();
 
         if config.flags & configure::VERBOSE != 0 {
@@ -94,43 +156,42 @@ fn main() {
             let command = command.clone();
             let inputs = inputs.clone();
             let outputs = outputs.clone();
-            let kill = kill.clone();
             let parked = parked.clone();
+            let running = running.clone();
+            let deadline = deadline.clone();
             let slot_id = slot_id.clone();
             let handle = thread::spawn(move || {
-                slot_event(
-                    slot_id.fetch_add(1, Ordering::SeqCst),
-                    command,
-                    inputs,
-                    outputs,
-                    kill,
-                    parked,
-                )
+                let id = slot_id.fetch_add(1, Ordering::SeqCst);
+                slot_event(id, command, inputs, outputs, parked, running, deadline)
             });
             handles.push(handle);
         }
     }
 
-    // Spawn slots for submitting inputs to each external node.
-    for node in &nodes {
+    if config.window.map_or(false, |window| window > MAX_WINDOW) {
+        eprintln!("concurr [WARN]: nodes accept no more than {} inputs at a time", MAX_WINDOW);
+    }
+
+    // Spawn a slot for submitting inputs to each external node.
+    for (index, node) in nodes.iter().enumerate() {
+        let window = config.window.unwrap_or(node.cores).max(1).min(MAX_WINDOW);
         if config.flags & configure::VERBOSE != 0 {
-            eprintln!("concurr [INFO]: spawning {} slots on {:?}", node.cores, node.address);
-        }
-        let domain = Arc::new(node.domain.clone());
-        for _ in 0..node.cores {
             let address = node.address;
-            let id = node.command;
-            let inputs = inputs.clone();
-            let outputs = outputs.clone();
-            let errors = errors.clone();
-            let failed = failed.clone();
-            let kill = kill.clone();
-            let domain = domain.clone();
-            let handle = thread::spawn(move || {
-                Slot::new(inputs, outputs, errors, failed, kill, address, id, &domain).spawn()
-            });
-            handles.push(handle);
+            eprintln!("concurr [INFO]: submitting {} inputs at a time to {:?}", window, address);
         }
+        let address = node.address;
+        let id = node.command;
+        let domain = node.domain.clone();
+        let framed = node.hello.supports(protocol::FRAMING);
+        let streams = framed && node.hello.supports(protocol::STREAMING);
+        let inputs = inputs.clone();
+        let outputs = outputs.clone();
+        let handle = thread::spawn(move || {
+            let node = index + 1;
+            Slot::new(inputs, outputs, node, address, id, &domain, window, framed, streams)
+                .spawn()
+        });
+        handles.push(handle);
     }
 
     // Useful for signaling the total number of inputs that are to be expected.
@@ -139,84 +200,177 @@ fn main() {
 
     // Pass arguments into the spawned threads, according to the type of arguments that are
     // have been supplied, and where the arguments originate from.
+    let colsep = arguments.colsep;
+    let format = arguments.format;
     match arguments.args {
         ArgsSource::RedirFile(path) => {
+            let inputs = inputs.clone();
+            let outputs = outputs.clone();
             let total_inputs = total_inputs.clone();
             let inputs_finished = inputs_finished.clone();
             thread::spawn(move || {
                 let ninputs = &mut 0;
-                source::file(&inputs, &path, ninputs);
+                let colsep = colsep.as_ref().map(String::as_str);
+                source::file(&inputs, &path, format, colsep, ninputs);
                 total_inputs.store(*ninputs, Ordering::SeqCst);
                 inputs_finished.store(true, Ordering::SeqCst);
+                outputs.outputs.wake();
             });
         }
         ArgsSource::RedirPipe => {
+            let inputs = inputs.clone();
+            let outputs = outputs.clone();
             let total_inputs = total_inputs.clone();
             let inputs_finished = inputs_finished.clone();
             thread::spawn(move || {
                 let ninputs = &mut 0;
-                source::stdin(&inputs, ninputs);
+                source::stdin(&inputs, format, colsep.as_ref().map(String::as_str), ninputs);
                 total_inputs.store(*ninputs, Ordering::SeqCst);
                 inputs_finished.store(true, Ordering::SeqCst);
+                outputs.outputs.wake();
             });
         }
         ArgsSource::Cli(args) => {
-            // This branch will generate permutations of the inputs to use as inputs.
-            if args.len() != 1 {
-                unimplemented!("permutations currently unsupported")
-            }
-
+            let inputs = inputs.clone();
+            let outputs = outputs.clone();
             let total_inputs = total_inputs.clone();
             let inputs_finished = inputs_finished.clone();
-            thread::spawn(move || match args[0] {
-                ArgUnit::Strings(ref vec) => {
-                    let mut ninputs = 0;
-                    for ref input in vec.iter() {
-                        inputs.insert_job(ninputs, String::from(input.as_str()));
-                        ninputs += 1;
-                    }
-                    total_inputs.store(ninputs, Ordering::SeqCst);
-                    inputs_finished.store(true, Ordering::SeqCst);
-                }
-                ArgUnit::Files(ref vec) => {
-                    let ninputs = &mut 0;
-                    for path in vec.iter() {
-                        source::file(&inputs, &Path::new(path), ninputs);
+            thread::spawn(move || {
+                let ninputs = &mut 0;
+                if let (1, Some(&ArgUnit::Files(ref paths))) = (args.len(), args.first()) {
+                    // A single group of files can simply be streamed into the inputs buffer.
+                    for path in paths {
+                        let colsep = colsep.as_ref().map(String::as_str);
+                        source::file(&inputs, Path::new(path), format, colsep, ninputs);
                     }
-                    total_inputs.store(*ninputs, Ordering::SeqCst);
-                    inputs_finished.store(true, Ordering::SeqCst);
+                } else {
+                    // Otherwise, the permutations of every group will be generated as inputs.
+                    let colsep = colsep.as_ref().map(String::as_str);
+                    source::permutations(&inputs, args, format, colsep, ninputs);
                 }
+                total_inputs.store(*ninputs, Ordering::SeqCst);
+                inputs_finished.store(true, Ordering::SeqCst);
+                outputs.outputs.wake();
             });
         }
     }
 
-    let stdout = io::stdout();
-    let stdout = &mut stdout.lock();
+    let joblog = match arguments.joblog {
+        Some(ref path) => match JobLog::create(path, arguments.resume.is_some()) {
+            Ok(joblog) => Some(joblog),
+            Err(why) => {
+                eprintln!("concurr [CRITICAL]: unable to create {:?}: {}", path, why);
+                exit(summary::ERROR_STATUS);
+            }
+        },
+        None => None,
+    };
+
+    // Displays the progress of the jobs on stderr, until every job has been written.
+    let stop_progress = Arc::new(AtomicBool::new(false));
+    let progress = arguments.progress.map(|mode| {
+        let inputs = inputs.clone();
+        let hosts = hosts.clone();
+        let counts = (inputs_finished.clone(), total_inputs.clone());
+        let screen = screen.clone().unwrap();
+        let stop = stop_progress.clone();
+        thread::spawn(move || progress::display(inputs, hosts, mode, counts, screen, stop))
+    });
+
+    let results = match arguments.results {
+        Some(ref dir) => match Results::create(dir, arguments.resume.is_some()) {
+            Ok(results) => Some(results),
+            Err(why) => {
+                eprintln!("concurr [CRITICAL]: unable to create {:?}: {}", dir, why);
+                exit(summary::ERROR_STATUS);
+            }
+        },
+        None => None,
+    };
+
     let mut counter = 0;
+    let mut received = 0;
+    let mut halted = None;
+    let verbose = config.flags & configure::VERBOSE != 0;
+    let mut reporter = Reporter {
+        summary: Summary::default(),
+        joblog,
+        results,
+        hosts,
+        verbose,
+        screen,
+        tag,
+    };
     let start = Instant::now();
 
-    // Wait for inputs to be received, exiting the program once all inputs have been processed.
-    while !(inputs_finished.load(Ordering::Relaxed)
-        && counter == total_inputs.load(Ordering::Relaxed))
-    {
-        // The get method blocks until the next output is found.
-        match outputs.get(&counter) {
-            Output::Outcome(status, mut source) => {
-                if config.flags & configure::VERBOSE != 0 {
-                    let _ = writeln!(stdout, "\nconcurr [INFO] Job {}: {}", counter, status);
-                }
-                source.write(stdout);
+    // Wait for outputs to be received, exiting the program once all inputs have been processed.
+    loop {
+        let (id, output) = if arguments.output_mode == OutputMode::Keep {
+            // Jobs that are skipped will never have an output.
+            if inputs.skips(counter) {
+                counter += 1;
+                continue;
+            }
+
+            // Blocks until the next output is found, or every output has been written.
+            let done = || {
+                inputs_finished.load(Ordering::SeqCst)
+                    && counter >= total_inputs.load(Ordering::SeqCst)
+            };
+            match outputs.get(counter, done) {
+                Some(output) => (counter, output),
+                None => break,
             }
-            Output::Failed => {
-                eprintln!("Job {} failed to execute", counter);
+        } else {
+            // Outputs are written in the order that their jobs completed.
+            let done = || received_all(&inputs, &inputs_finished, &total_inputs, received);
+            match outputs.next(done) {
+                Some(output) => output,
+                None => break,
             }
-        }
+        };
+
+        reporter.write(id, output);
         counter += 1;
+        received += 1;
+
+        // Stop dispatching inputs once the halt policy has been met.
+        if arguments.halt.map_or(false, |halt| halt.check(&reporter.summary)) {
+            halted = Some(id);
+            break;
+        }
+    }
+
+    if let Some(id) = halted {
+        eprintln!("concurr [INFO]: halting, as job {} met the halt policy", id);
+        inputs.close();
+
+        // Jobs that are running will be killed, rather than being allowed to complete.
+        if arguments.halt.map(|halt| halt.when) == Some(When::Now) {
+            for node in nodes.iter_mut().filter(|node| node.hello.supports(protocol::KILL)) {
+                if let Err(why) = node.kill() {
+                    let address = node.address;
+                    eprintln!("concurr [CRITICAL]: unable to kill jobs on {}: {}", address, why);
+                }
+            }
+            running.kill_all();
+        }
+
+        // Then write the outputs of the jobs that were dispatched before the halt.
+        handles.drain(..).for_each(|h| h.join().unwrap());
+        for (id, output) in outputs.drain() {
+            reporter.write(id, output);
+        }
     }
 
     let time = Instant::now() - start;
 
-    if config.flags & configure::VERBOSE != 0 {
+    if let Some(handle) = progress {
+        stop_progress.store(true, Ordering::SeqCst);
+        handle.join().unwrap();
+    }
+
+    if verbose {
         eprintln!(
             "concurr [INFO]: processed {} inputs within {}.{}s",
             total_inputs.load(Ordering::Relaxed),
@@ -225,7 +379,65 @@ fn main() {
         );
     }
 
+    let summary = reporter.summary;
+    if summary.failed() || verbose {
+        eprintln!("concurr [INFO]: {}", summary);
+    }
+
     // Stop the threads that are running in the background.
-    kill.store(true, Ordering::Relaxed);
+    inputs.close();
     handles.into_iter().for_each(|h| h.join().unwrap());
+    // Delete the command from each node before exiting.
+    drop(nodes);
+    exit(summary.exit_status(arguments.exit_policy));
+}
+
+/// Whether an output has been received for every job, excluding those that were skipped. The
+/// total is stored before the inputs are marked as finished, so it is only loaded afterwards,
+/// lest a total that was loaded before the inputs finished be taken for the final total.
+fn received_all(
+    inputs: &Inputs,
+    finished: &AtomicBool,
+    total: &AtomicUsize,
+    received: usize,
+) -> bool {
+    finished.load(Ordering::SeqCst) && {
+        let total = total.load(Ordering::SeqCst);
+        received >= total - inputs.skipped(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::received_all;
+    use inputs::{Inputs, MAX_PENDING};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn completion() {
+        let inputs = Inputs::new(0, false, HashSet::new(), 1, MAX_PENDING);
+        let finished = Arc::new(AtomicBool::new(false));
+        let total = Arc::new(AtomicUsize::new(0));
+
+        // Inputs are read slowly, while the outputs of the first two have been received.
+        let reader = {
+            let (finished, total) = (finished.clone(), total.clone());
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                total.store(3, Ordering::SeqCst);
+                finished.store(true, Ordering::SeqCst);
+            })
+        };
+        while !finished.load(Ordering::SeqCst) {
+            assert!(!received_all(&inputs, &finished, &total, 2));
+        }
+        reader.join().unwrap();
+
+        assert!(!received_all(&inputs, &finished, &total, 2));
+        assert!(received_all(&inputs, &finished, &total, 3));
+    }
 }
@@ -1,14 +1,27 @@
+use concurr::{Limit, Mode, Timeout};
+use concurr::protocol;
 use connection::{Connection, ConnectionError};
 use std::net::SocketAddr;
 
 pub fn get<NODES: Iterator<Item = (SocketAddr, String)>>(
     nodes: NODES,
     command: &str,
+    mode: Mode,
+    timeout: Option<Timeout>,
+    limit: Option<Limit>,
 ) -> Result<Vec<Connection>, ConnectionError> {
     let mut output = Vec::new();
     for (addr, domain) in nodes {
         let mut conn = Connection::new(addr, domain)?;
-        conn.send_command(command)?;
+        if mode == Mode::Exec && !conn.hello.supports(protocol::EXEC) {
+            return Err(ConnectionError::Unsupported(addr, "executing commands without a shell"));
+        }
+        if timeout.is_some() && !conn.hello.supports(protocol::TIMEOUT) {
+            return Err(ConnectionError::Unsupported(addr, "timeouts"));
+        }
+        // Nodes which are unable to limit outputs send them in full, to be truncated here.
+        let limit = if conn.hello.supports(protocol::LIMIT) { limit } else { None };
+        conn.send_command(command, mode, timeout, limit)?;
         output.push(conn);
     }
 
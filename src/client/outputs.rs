@@ -1,75 +1,575 @@
-use chashmap::CHashMap;
-use concurr::InsertOutput;
+use super::Inputs;
+use super::inputs::LOCAL;
+use concurr::{anonymous_file, Execution, InsertOutput, Limit, Mailbox, Stream};
+use progress::Screen;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, StdoutLock, Write};
-use std::thread;
-use std::time::Duration;
+use std::io::{self, Read, Seek, SeekFrom, StdoutLock, Write};
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tag::{Tag, Tagged};
+
+/// Determines when, and in what order, the outputs of each job are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputMode {
+    /// The outputs of each job are written once it has completed, in the order of their IDs.
+    Keep,
+    /// The outputs of each job are written as soon as it has completed.
+    Completion,
+    /// Each line that a job writes is written as soon as it is complete, while the job runs.
+    Line,
+}
+
+impl FromStr for OutputMode {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<OutputMode, ()> {
+        match input {
+            "keep" => Ok(OutputMode::Keep),
+            "completion" => Ok(OutputMode::Completion),
+            "line" => Ok(OutputMode::Line),
+            _ => Err(()),
+        }
+    }
+}
 
 /// Enables efficiently handling outputs based on it's source.
 pub enum OutputSource {
-    /// Outputs created from the internal node are stored within anonymous files.
+    /// Outputs created from the internal node are stored within anonymous files, until they
+    /// have been copied into the spill.
     Internal(File, File),
-    /// Outputs from external nodes, on the other hand, must be buffered to a string.
-    External(String, String),
+    /// Outputs from external nodes, on the other hand, are buffered in memory.
+    External(Vec<u8>, Vec<u8>),
+    /// Outputs from the internal node, and those from external nodes which did not fit within
+    /// the memory budget.
+    Spilled(Section),
+    /// Outputs which were written while the job was running, and the number of bytes that
+    /// were written to each.
+    Streamed(u64, u64),
 }
 
 impl OutputSource {
-    /// Handles writing the input streams to their corresponding output streams.
-    pub fn write(&mut self, stdout: &mut StdoutLock) {
+    /// Handles writing the input streams to their corresponding output streams, returning the
+    /// number of bytes that were written to each. When given, each line is preceded by the tag.
+    pub fn write(&mut self, stdout: &mut StdoutLock, tag: Option<&str>) -> (u64, u64) {
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        let bytes = match tag {
+            Some(tag) => self.copy(&mut Tagged::new(stdout, tag), &mut Tagged::new(stderr, tag)),
+            None => self.copy(stdout, &mut stderr),
+        };
+        bytes.unwrap_or((0, 0))
+    }
+
+    /// Copies the standard output and error of the job into the given writers, returning the
+    /// number of bytes that were copied into each.
+    pub fn copy<OUT: Write, ERR: Write>(
+        &mut self,
+        stdout: &mut OUT,
+        stderr: &mut ERR,
+    ) -> io::Result<(u64, u64)> {
         match *self {
             OutputSource::Internal(ref mut out, ref mut err) => {
-                let mut stderr = io::stderr();
-                let _ = io::copy(out, stdout);
-                let _ = io::copy(err, &mut stderr.lock());
+                Ok((io::copy(out, stdout)?, io::copy(err, stderr)?))
+            }
+            OutputSource::External(ref out, ref err) => {
+                stdout.write_all(out)?;
+                stderr.write_all(err)?;
+                Ok((out.len() as u64, err.len() as u64))
+            }
+            OutputSource::Spilled(ref section) => section.copy(stdout, stderr),
+            OutputSource::Streamed(out, err) => Ok((out, err)),
+        }
+    }
+}
+
+/// A file which the outputs that do not fit within the memory budget are appended to, so that
+/// spilling does not require a pair of file descriptors for each job. The file is emptied each
+/// time that every output which was spilled to it has been written.
+#[derive(Clone, Default)]
+pub struct Spill {
+    file: Arc<Mutex<SpillFile>>,
+}
+
+#[derive(Default)]
+struct SpillFile {
+    /// The anonymous file, which is created once it is first needed.
+    file:    Option<File>,
+    /// The number of bytes that have been spilled to the file.
+    end:     u64,
+    /// The number of sections of the file that have yet to be dropped.
+    pending: usize,
+}
+
+impl Spill {
+    /// Appends the outputs of a job to the file.
+    fn append(&self, out: &[u8], err: &[u8]) -> io::Result<Section> {
+        self.append_from(&mut &out[..], &mut &err[..])
+    }
+
+    /// Appends the outputs of a job to the file, reading each of them to their end.
+    fn append_from<OUT: Read, ERR: Read>(
+        &self,
+        out: &mut OUT,
+        err: &mut ERR,
+    ) -> io::Result<Section> {
+        let mut spill = self.file.lock().unwrap();
+        if spill.file.is_none() {
+            spill.file = Some(anonymous_file()?);
+        }
+        let offset = spill.end;
+        let (out, err) = {
+            let mut file = spill.file.as_ref().unwrap();
+            let copied = file
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| io::copy(out, &mut file))
+                .and_then(|out| io::copy(err, &mut file).map(|err| (out, err)));
+            match copied {
+                Ok(lengths) => lengths,
+                Err(why) => {
+                    let _ = file.set_len(offset);
+                    return Err(why);
+                }
             }
-            OutputSource::External(ref mut out, ref mut err) => {
-                let mut stderr = io::stderr();
-                let _ = stdout.write_all(out.as_bytes());
-                let _ = stderr.lock().write_all(err.as_bytes());
+        };
+        spill.end += out + err;
+        spill.pending += 1;
+        Ok(Section {
+            spill: self.clone(),
+            offset,
+            out,
+            err,
+        })
+    }
+}
+
+/// The outputs of a job which were spilled, stored as a section of the spill file.
+pub struct Section {
+    spill:  Spill,
+    offset: u64,
+    /// The number of bytes of stdout, which are followed by those of stderr.
+    out:    u64,
+    err:    u64,
+}
+
+impl Section {
+    fn copy<OUT: Write, ERR: Write>(
+        &self,
+        stdout: &mut OUT,
+        stderr: &mut ERR,
+    ) -> io::Result<(u64, u64)> {
+        let spill = self.spill.file.lock().unwrap();
+        let file = spill.file.as_ref().expect("spilled outputs without a spill file");
+        copy_range(file, self.offset, self.out, stdout)?;
+        copy_range(file, self.offset + self.out, self.err, stderr)?;
+        Ok((self.out, self.err))
+    }
+}
+
+impl Drop for Section {
+    fn drop(&mut self) {
+        let mut spill = self.spill.file.lock().unwrap();
+        spill.pending -= 1;
+        if spill.pending == 0 {
+            if let Some(ref file) = spill.file {
+                let _ = file.set_len(0);
             }
+            spill.end = 0;
+        }
+    }
+}
+
+/// Copies the given number of bytes of the file, starting from the offset, into the output.
+fn copy_range<W: Write>(file: &File, offset: u64, length: u64, output: &mut W) -> io::Result<()> {
+    let mut buffer = [0; 8 * 1024];
+    let (mut offset, end) = (offset, offset + length);
+    while offset < end {
+        let wanted = (end - offset).min(buffer.len() as u64) as usize;
+        let read = file.read_at(&mut buffer[..wanted], offset)?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "spill file was truncated"));
+        }
+        output.write_all(&buffer[..read])?;
+        offset += read as u64;
+    }
+    Ok(())
+}
+
+/// The number of bytes of outputs from external nodes which may be buffered in memory while
+/// they wait to be written. Outputs which would exceed the budget are spilled to a file
+/// instead.
+pub struct Budget {
+    limit: usize,
+    used:  AtomicUsize,
+}
+
+impl Budget {
+    pub fn new(limit: Limit) -> Budget {
+        Budget {
+            limit: limit.0 as usize,
+            used:  AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserves the bytes, unless they would exceed the budget.
+    fn reserve(&self, bytes: usize) -> bool {
+        if self.used.fetch_add(bytes, Ordering::SeqCst) + bytes <= self.limit {
+            return true;
         }
+        self.release(bytes);
+        false
     }
+
+    /// Reserves the bytes, regardless of the budget.
+    fn claim(&self, bytes: usize) { self.used.fetch_add(bytes, Ordering::SeqCst); }
+
+    fn release(&self, bytes: usize) { self.used.fetch_sub(bytes, Ordering::SeqCst); }
+}
+
+/// Writes each complete line of the jobs that are running, as they are produced. Lines from
+/// different jobs are interleaved, but a line is never split.
+pub struct Lines {
+    partial: Mutex<HashMap<usize, Partial>>,
+    /// Where the progress is drawn, which is erased while lines are being written.
+    screen:  Option<Arc<Screen>>,
+    tag:     Option<Tag>,
+    limit:   Option<Limit>,
 }
 
+/// The outputs of a job which do not yet end with a newline, and so have yet to be written.
+#[derive(Default)]
+struct Partial {
+    out:     Vec<u8>,
+    err:     Vec<u8>,
+    /// The number of bytes that have been written to stdout and stderr.
+    written: (u64, u64),
+    /// The number of bytes that the job has written to stdout and stderr, including those
+    /// which exceeded the limit.
+    seen:    (u64, u64),
+    /// The tag of the job, which is expanded once its first chunk has been received.
+    tag:     Option<String>,
+}
+
+impl Lines {
+    pub fn new(screen: Option<Arc<Screen>>, tag: Option<Tag>, limit: Option<Limit>) -> Lines {
+        Lines {
+            partial: Mutex::new(HashMap::new()),
+            screen,
+            tag,
+            limit,
+        }
+    }
+
+    /// Writes the lines of the chunk which are complete. The input of the job is only obtained
+    /// if the job needs to be tagged.
+    pub fn write<F: FnOnce() -> Vec<String>>(
+        &self,
+        id: usize,
+        node: usize,
+        stream: Stream,
+        chunk: &[u8],
+        input: F,
+    ) {
+        let mut partial = self.partial.lock().unwrap();
+        let partial = partial.entry(id).or_insert_with(|| Partial {
+            tag: self.tag.as_ref().map(|tag| tag.expand(id, &input(), node)),
+            ..Partial::default()
+        });
+        let (buffer, written, seen) = match stream {
+            Stream::Stdout => (&mut partial.out, &mut partial.written.0, &mut partial.seen.0),
+            Stream::Stderr => (&mut partial.err, &mut partial.written.1, &mut partial.seen.1),
+        };
+        let lines = match self.limit {
+            Some(limit) => complete_lines(buffer, &limit.keep(seen, chunk)),
+            None => complete_lines(buffer, chunk),
+        };
+        *written += lines.len() as u64;
+        self.emit(stream, &lines, partial.tag.as_ref().map(String::as_str));
+    }
+
+    /// Writes the remainder of the outputs of the job, which may not end with a newline, and
+    /// returns the number of bytes that were written to each of stdout and stderr. If nothing
+    /// was received from the job, `None` is returned.
+    pub fn finish(&self, id: usize) -> Option<(u64, u64)> {
+        let partial = self.partial.lock().unwrap().remove(&id)?;
+        let tag = partial.tag.as_ref().map(String::as_str);
+        self.emit(Stream::Stdout, &partial.out, tag);
+        self.emit(Stream::Stderr, &partial.err, tag);
+        let (out, err) = partial.written;
+        Some((out + partial.out.len() as u64, err + partial.err.len() as u64))
+    }
+
+    fn emit(&self, stream: Stream, bytes: &[u8], tag: Option<&str>) {
+        if bytes.is_empty() {
+            return;
+        }
+        let _erased = self.screen.as_ref().map(|screen| screen.erase());
+        let _ = match stream {
+            Stream::Stdout => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                write_tagged(&mut stdout, bytes, tag).and_then(|_| stdout.flush())
+            }
+            Stream::Stderr => write_tagged(&mut io::stderr(), bytes, tag),
+        };
+    }
+}
+
+fn write_tagged<W: Write>(output: &mut W, bytes: &[u8], tag: Option<&str>) -> io::Result<()> {
+    match tag {
+        Some(tag) => Tagged::new(output, tag).write_all(bytes),
+        None => output.write_all(bytes),
+    }
+}
+
+/// Discards the bytes of the file which exceed the limit, marking where it was truncated.
+fn truncate(file: &mut File, limit: Limit) -> io::Result<()> {
+    if file.metadata()?.len() > limit.0 {
+        file.set_len(limit.0)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(limit.marker().as_bytes())?;
+        file.seek(SeekFrom::Start(0))?;
+    }
+    Ok(())
+}
+
+/// Appends the chunk to the buffer, and then takes every complete line from it.
+fn complete_lines(buffer: &mut Vec<u8>, chunk: &[u8]) -> Vec<u8> {
+    buffer.extend_from_slice(chunk);
+    match buffer.iter().rposition(|&byte| byte == b'\n') {
+        Some(end) => {
+            let rest = buffer.split_off(end + 1);
+            mem::replace(buffer, rest)
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Only the outputs of the final attempt of each job are stored. The outputs of attempts which
+/// are to be retried are discarded.
 pub struct Outputs {
-    pub outputs: CHashMap<usize, Output>,
+    pub outputs: Mailbox<Output>,
+    pub inputs:  Arc<Inputs>,
+    /// When given, the outputs of jobs on the client, and on nodes which stream outputs, are
+    /// written line by line as they run, and so the outputs of every attempt are written.
+    pub lines:   Option<Lines>,
+    /// The number of bytes of each output of a job that are kept.
+    pub limit:   Option<Limit>,
+    /// Limits the outputs from external nodes that are buffered while they wait to be written.
+    pub budget:  Budget,
+    /// Where the outputs which exceed the budget are stored instead.
+    pub spill:   Spill,
+}
+
+/// Describes the final attempt of a job that was executed.
+pub struct Details {
+    pub execution: Execution,
+    /// The attempt that these outputs belong to, starting from `1`.
+    pub attempt:   u8,
+    /// The node that the job was executed on.
+    pub node:      usize,
+    /// The value of each input source.
+    pub input:     Vec<String>,
 }
 
 pub enum Output {
-    Outcome(u8, OutputSource),
-    Failed,
+    Outcome(Details, OutputSource),
+    /// The job could not be executed on the node with the given ID.
+    Failed(usize),
 }
 
 impl Outputs {
     /// Appends a new output onto the queue from an external source
-    pub fn push_external(&self, id: usize, status: u8, out: String, err: String) {
-        // Simply wrap the inputs as an `External` variant.
-        let source = OutputSource::External(out, err);
-        let output = Output::Outcome(status, source);
-        self.outputs.insert(id, output);
-    }
-
-    /// Loops until the next output has been found. For each unsuccessful loop, the thread
-    /// will wait 1ms before attempting to lock and grab the output again.
-    pub fn get(&self, id: &usize) -> Output {
-        loop {
-            if let Some(element) = self.outputs.remove(id) {
-                return element;
+    pub fn push_external(
+        &self,
+        id: usize,
+        node: usize,
+        execution: Execution,
+        out: Vec<u8>,
+        err: Vec<u8>,
+    ) {
+        // Outputs which were streamed have already been written as lines.
+        let streamed = self.lines.as_ref().and_then(|lines| lines.finish(id));
+        let source = match streamed {
+            Some((out, err)) => OutputSource::Streamed(out, err),
+            None => OutputSource::External(out, err),
+        };
+        self.push(id, node, execution, source);
+    }
+
+    /// Records that the job could not be executed, or transmitted to a node.
+    pub fn push_failed(&self, id: usize, node: usize) {
+        let _ = self.inputs.finish(id, None, node);
+        self.inputs.progress.completed(node, None);
+        self.outputs.insert(id, Output::Failed(node));
+    }
+
+    fn push(&self, id: usize, node: usize, execution: Execution, source: OutputSource) {
+        match self.inputs.finish(id, Some(execution.status), node) {
+            Some((attempt, input)) => {
+                self.inputs.progress.completed(node, Some(&execution));
+                let source = self.reserve(id, source);
+                let details = Details {
+                    execution,
+                    attempt,
+                    node,
+                    input,
+                };
+                self.outputs.insert(id, Output::Outcome(details, source));
             }
-            thread::sleep(Duration::from_millis(1));
+            None => self.inputs.progress.retried(node),
         }
     }
+
+    /// Outputs which are buffered in memory are spilled to a file once the budget has been
+    /// exhausted. If they could not be spilled, they remain in memory.
+    fn reserve(&self, id: usize, source: OutputSource) -> OutputSource {
+        let (out, err) = match source {
+            OutputSource::External(out, err) => (out, err),
+            source => return source,
+        };
+        let bytes = out.len() + err.len();
+        if self.budget.reserve(bytes) {
+            return OutputSource::External(out, err);
+        }
+        match self.spill.append(&out, &err) {
+            Ok(section) => OutputSource::Spilled(section),
+            Err(why) => {
+                eprintln!("concurr [WARN]: unable to spill outputs of job {}: {}", id, why);
+                self.budget.claim(bytes);
+                OutputSource::External(out, err)
+            }
+        }
+    }
+
+    /// The outputs of jobs on the client are copied into the spill, so that those which are
+    /// waiting to be written do not each hold a pair of files open. If they could not be
+    /// copied, the files are kept instead.
+    fn spill_internal(&self, id: usize, mut out: File, mut err: File) -> OutputSource {
+        match self.spill.append_from(&mut out, &mut err) {
+            Ok(section) => OutputSource::Spilled(section),
+            Err(why) => {
+                eprintln!("concurr [WARN]: unable to spill outputs of job {}: {}", id, why);
+                let _ = out.seek(SeekFrom::Start(0)).and_then(|_| err.seek(SeekFrom::Start(0)));
+                OutputSource::Internal(out, err)
+            }
+        }
+    }
+
+    /// Outputs which are taken are about to be written, and so no longer count towards the
+    /// budget.
+    fn taken(&self, output: &Output) {
+        if let Output::Outcome(_, OutputSource::External(ref out, ref err)) = *output {
+            self.budget.release(out.len() + err.len());
+        }
+    }
+
+    /// Parks until the output with the given ID has been inserted, or `done` returns `true`.
+    pub fn get<F: Fn() -> bool>(&self, id: usize, done: F) -> Option<Output> {
+        let output = self.outputs.remove_unless(id, done);
+        if let Some(ref output) = output {
+            self.taken(output);
+        }
+        output
+    }
+
+    /// Parks until any output has been inserted, or `done` returns `true`.
+    pub fn next<F: Fn() -> bool>(&self, done: F) -> Option<(usize, Output)> {
+        let output = self.outputs.remove_any(done);
+        if let Some((_, ref output)) = output {
+            self.taken(output);
+        }
+        output
+    }
+
+    /// Takes every output that has been inserted, ordered by their job IDs.
+    pub fn drain(&self) -> Vec<(usize, Output)> {
+        let outputs = self.outputs.drain();
+        outputs.iter().for_each(|&(_, ref output)| self.taken(output));
+        outputs
+    }
 }
 
 impl InsertOutput for Outputs {
     /// Appends a new internal output onto the queue.
-    fn insert(&self, id: usize, mut result: Option<(u8, File, File)>) {
-        let output = match result.take() {
-            Some((sts, out, err)) => {
-                Output::Outcome(sts, OutputSource::Internal(out, err))
+    fn insert(&self, id: usize, mut result: Option<(Execution, File, File)>) {
+        let streamed = self.lines.as_ref().and_then(|lines| lines.finish(id));
+        match result.take() {
+            Some((exe, mut out, mut err)) => {
+                if let (Some(limit), None) = (self.limit, streamed) {
+                    let _ = truncate(&mut out, limit).and_then(|_| truncate(&mut err, limit));
+                }
+                let source = match streamed {
+                    Some((out, err)) => OutputSource::Streamed(out, err),
+                    None => self.spill_internal(id, out, err),
+                };
+                self.push(id, LOCAL, exe, source)
             }
-            None => Output::Failed,
+            None => self.push_failed(id, LOCAL),
+        }
+    }
+
+    fn streams(&self, _id: usize) -> bool { self.lines.is_some() }
+
+    fn insert_chunk(&self, id: usize, stream: Stream, chunk: &[u8]) {
+        if let Some(ref lines) = self.lines {
+            lines.write(id, LOCAL, stream, chunk, || self.inputs.input(id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete_lines, Spill};
+    use concurr::anonymous_file;
+    use std::fs;
+    use std::io::{Seek, SeekFrom, Write};
+
+    #[test]
+    fn lines() {
+        let buffer = &mut Vec::new();
+        assert_eq!(complete_lines(buffer, b"a"), b"");
+        assert_eq!(complete_lines(buffer, b"b\nc\nd"), b"ab\nc\n");
+        assert_eq!(complete_lines(buffer, b"\n"), b"d\n");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn spill() {
+        // The outputs of local jobs are spilled from their files, and those of external jobs
+        // from memory, yet every section shares the one file.
+        let files = || fs::read_dir("/proc/self/fd").unwrap().count();
+        let (before, jobs) = (files(), 256);
+        let file = |contents: &[u8]| {
+            let mut file = anonymous_file().unwrap();
+            file.write_all(contents).unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file
         };
-        self.outputs.insert(id, output);
+
+        let spill = Spill::default();
+        let sections = (0..jobs)
+            .map(|id| {
+                let out = id.to_string();
+                if id % 2 == 0 {
+                    return spill.append(out.as_bytes(), b"err").unwrap();
+                }
+                spill.append_from(&mut file(out.as_bytes()), &mut file(b"err")).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(files() < before + jobs);
+
+        for (id, section) in sections.iter().enumerate() {
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            assert_eq!(section.copy(&mut out, &mut err).unwrap().1, 3);
+            assert_eq!(out, id.to_string().into_bytes());
+            assert_eq!(err, b"err");
+        }
+
+        drop(sections);
+        assert_eq!(spill.file.lock().unwrap().end, 0);
     }
 }
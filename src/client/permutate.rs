@@ -0,0 +1,83 @@
+/// Lazily generates the cartesian product of every input source, GNU Parallel style.
+///
+/// The last source varies the fastest, so `: a b : 1 2` yields `a 1`, `a 2`, `b 1`, `b 2`.
+/// Only the indexes into each source are tracked, so the permutations are never all stored in
+/// memory at once.
+pub struct Permutator<'a> {
+    sources: &'a [Vec<String>],
+    indexes: Vec<usize>,
+    done:    bool,
+}
+
+impl<'a> Permutator<'a> {
+    pub fn new(sources: &'a [Vec<String>]) -> Permutator<'a> {
+        Permutator {
+            sources,
+            indexes: vec![0; sources.len()],
+            done: sources.is_empty() || sources.iter().any(|source| source.is_empty()),
+        }
+    }
+}
+
+impl<'a> Iterator for Permutator<'a> {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Vec<String>> {
+        if self.done {
+            return None;
+        }
+
+        let permutation = self.sources
+            .iter()
+            .zip(self.indexes.iter())
+            .map(|(source, &index)| source[index].clone())
+            .collect();
+
+        // Increment the indexes like an odometer, starting from the last source.
+        let mut position = self.sources.len();
+        loop {
+            if position == 0 {
+                self.done = true;
+                break;
+            }
+            position -= 1;
+            self.indexes[position] += 1;
+            if self.indexes[position] != self.sources[position].len() {
+                break;
+            }
+            self.indexes[position] = 0;
+        }
+
+        Some(permutation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Permutator;
+
+    #[test]
+    fn permutations() {
+        let sources = vec![
+            vec!["a".to_owned(), "b".to_owned()],
+            vec!["1".to_owned(), "2".to_owned(), "3".to_owned()],
+        ];
+
+        let expected = vec![
+            vec!["a", "1"],
+            vec!["a", "2"],
+            vec!["a", "3"],
+            vec!["b", "1"],
+            vec!["b", "2"],
+            vec!["b", "3"],
+        ];
+
+        assert_eq!(Permutator::new(&sources).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn empty_source() {
+        let sources = vec![vec!["a".to_owned()], Vec::new()];
+        assert_eq!(Permutator::new(&sources).next(), None);
+    }
+}
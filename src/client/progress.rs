@@ -0,0 +1,265 @@
+use super::Inputs;
+use concurr::{Execution, Seconds, Status};
+use libc;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the display is redrawn when stderr is a terminal.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// How often lines are printed when stderr is not a terminal.
+const LINE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Determines what is displayed while jobs are running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Only the overall progress, and the estimated time of completion.
+    Eta,
+    /// The progress of each node, followed by the overall progress.
+    Nodes,
+}
+
+/// Counts the jobs that each node is running, and has completed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NodeProgress {
+    pub running:   usize,
+    /// Jobs whose final attempt has completed, including those which failed.
+    pub completed: usize,
+    pub failed:    usize,
+    /// The sum of the runtimes of the jobs that have completed.
+    pub runtime:   Duration,
+}
+
+/// The progress of every node, by its ID.
+pub struct Progress {
+    nodes: Mutex<Vec<NodeProgress>>,
+}
+
+impl Progress {
+    pub fn new(nodes: usize) -> Progress {
+        Progress {
+            nodes: Mutex::new(vec![NodeProgress::default(); nodes]),
+        }
+    }
+
+    /// A job was dispatched to the node.
+    pub fn started(&self, node: usize) { self.nodes.lock().unwrap()[node].running += 1; }
+
+    /// A job stopped running on the node, but will be attempted again.
+    pub fn retried(&self, node: usize) { self.nodes.lock().unwrap()[node].running -= 1; }
+
+    /// The final attempt of a job completed on the node. Jobs which could not be executed
+    /// lack an execution.
+    pub fn completed(&self, node: usize, execution: Option<&Execution>) {
+        let progress = &mut self.nodes.lock().unwrap()[node];
+        progress.running -= 1;
+        progress.completed += 1;
+        match execution {
+            Some(execution) => {
+                progress.runtime += execution.timing.runtime;
+                if execution.status != Status::Exited(0) {
+                    progress.failed += 1;
+                }
+            }
+            None => progress.failed += 1,
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<NodeProgress> { self.nodes.lock().unwrap().clone() }
+}
+
+/// Where the progress is drawn. When stderr is a terminal, the progress is drawn beneath the
+/// outputs of the jobs, and is erased while they are being written, so that they will not be
+/// drawn over.
+pub struct Screen {
+    terminal: bool,
+    /// The number of lines that are currently drawn.
+    drawn:    Mutex<usize>,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen {
+            terminal: unsafe { libc::isatty(libc::STDERR_FILENO) } == 1,
+            drawn:    Mutex::new(0),
+        }
+    }
+
+    /// Erases the progress, which will not be drawn again until the guard has been dropped.
+    pub fn erase<'a>(&'a self) -> MutexGuard<'a, usize> {
+        let mut drawn = self.drawn.lock().unwrap();
+        if *drawn != 0 {
+            // Move back to the first line that was drawn, and clear everything after it.
+            let _ = write!(io::stderr(), "\x1b[{}A\r\x1b[J", *drawn);
+            *drawn = 0;
+        }
+        drawn
+    }
+
+    fn draw(&self, lines: &[String]) {
+        let mut drawn = self.erase();
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        for line in lines {
+            let _ = if self.terminal {
+                writeln!(stderr, "{}", line)
+            } else {
+                writeln!(stderr, "concurr [INFO]: {}", line)
+            };
+        }
+        if self.terminal {
+            *drawn = lines.len();
+        }
+    }
+}
+
+/// Displays the progress on stderr until `stop` is set, redrawing it in place when stderr is a
+/// terminal, and otherwise printing it periodically. The total number of jobs is unknown until
+/// every input has been read, and so is the estimated time of completion.
+pub fn display(
+    inputs: Arc<Inputs>,
+    hosts: Vec<String>,
+    mode: Mode,
+    (finished, total): (Arc<AtomicBool>, Arc<AtomicUsize>),
+    screen: Arc<Screen>,
+    stop: Arc<AtomicBool>,
+) {
+    let interval = if screen.terminal { REDRAW_INTERVAL } else { LINE_INTERVAL };
+    let start = Instant::now();
+    let mut last = start;
+
+    loop {
+        let stopping = stop.load(Ordering::SeqCst);
+        if stopping || Instant::now() - last >= interval {
+            last = Instant::now();
+            let total = if finished.load(Ordering::SeqCst) {
+                let total = total.load(Ordering::SeqCst);
+                Some(total - inputs.skipped(total))
+            } else {
+                None
+            };
+
+            let nodes = inputs.progress.snapshot();
+            screen.draw(&render(&nodes, &hosts, mode, last - start, total));
+        }
+
+        if stopping {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Generates one line for each node which has been given a job, followed by the overall
+/// progress, or only the latter.
+fn render(
+    nodes: &[NodeProgress],
+    hosts: &[String],
+    mode: Mode,
+    elapsed: Duration,
+    total: Option<usize>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    if mode == Mode::Nodes {
+        let width = hosts.iter().map(String::len).max().unwrap_or(0);
+        for (node, host) in nodes.iter().zip(hosts) {
+            if node.running == 0 && node.completed == 0 {
+                continue;
+            }
+            let average = match node.completed {
+                0 => "-".to_owned(),
+                completed => format!("{}s", Seconds(node.runtime / completed as u32)),
+            };
+            lines.push(format!(
+                "{:width$}  running {}, completed {}, failed {}, average {}",
+                host,
+                node.running,
+                node.completed,
+                node.failed,
+                average,
+                width = width
+            ));
+        }
+    }
+
+    let running = nodes.iter().map(|node| node.running).sum::<usize>();
+    let completed = nodes.iter().map(|node| node.completed).sum::<usize>();
+    let failed = nodes.iter().map(|node| node.failed).sum::<usize>();
+    let (total, eta) = match total {
+        Some(total) => {
+            let remaining = total.saturating_sub(completed);
+            let eta = if remaining == 0 {
+                "done".to_owned()
+            } else if completed == 0 {
+                "unknown".to_owned()
+            } else {
+                // Assumes that the remaining jobs will complete at the rate of those before.
+                let millis = elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000;
+                let eta = millis * remaining as u64 / completed as u64;
+                clock(Duration::from_millis(eta))
+            };
+            (total.to_string(), eta)
+        }
+        None => ("?".to_owned(), "unknown".to_owned()),
+    };
+
+    lines.push(format!(
+        "{}/{} jobs completed, {} running, {} failed, ETA {}",
+        completed, total, running, failed, eta
+    ));
+    lines
+}
+
+/// Writes a duration as hours, minutes, and seconds, omitting the leading units that are zero.
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clock, render, Mode, NodeProgress};
+    use std::time::Duration;
+
+    #[test]
+    fn rendering() {
+        assert_eq!(clock(Duration::from_secs(7)), "7s");
+        assert_eq!(clock(Duration::from_secs(192)), "3m12s");
+        assert_eq!(clock(Duration::from_secs(3723)), "1h02m03s");
+
+        let nodes = [
+            NodeProgress::default(),
+            NodeProgress {
+                running:   2,
+                completed: 4,
+                failed:    1,
+                runtime:   Duration::from_secs(6),
+            },
+        ];
+        let hosts = [":".to_owned(), "10.0.0.2:31514".to_owned()];
+        let elapsed = Duration::from_secs(10);
+
+        // The estimate is unknown until the total number of jobs is known.
+        assert_eq!(
+            render(&nodes, &hosts, Mode::Nodes, elapsed, None),
+            vec![
+                "10.0.0.2:31514  running 2, completed 4, failed 1, average 1.500s",
+                "4/? jobs completed, 2 running, 1 failed, ETA unknown",
+            ]
+        );
+        assert_eq!(
+            render(&nodes, &hosts, Mode::Eta, elapsed, Some(10)),
+            vec!["4/10 jobs completed, 2 running, 1 failed, ETA 15s"]
+        );
+        assert_eq!(
+            render(&nodes, &hosts, Mode::Eta, elapsed, Some(4)),
+            vec!["4/4 jobs completed, 2 running, 1 failed, ETA done"]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use concurr::Status;
+use joblog::JobLog;
+use outputs::Output;
+use progress::Screen;
+use results::Results;
+use std::io::{self, Write};
+use std::sync::Arc;
+use summary::Summary;
+use tag::Tag;
+
+/// Writes the outputs of each job as they are received, and records their outcomes.
+pub struct Reporter {
+    pub summary: Summary,
+    pub joblog:  Option<JobLog>,
+    /// When given, outputs are stored within a directory tree, instead of being written.
+    pub results: Option<Results>,
+    /// The name of each node, by its ID, for the job log.
+    pub hosts:   Vec<String>,
+    pub verbose: bool,
+    /// Where the progress is drawn, if it is displayed.
+    pub screen:  Option<Arc<Screen>>,
+    /// Precedes each line of the outputs that are written, but not those that are saved.
+    pub tag:     Option<Tag>,
+}
+
+impl Reporter {
+    /// Writes the output of a job, and records its outcome within the summary and job log.
+    ///
+    /// The progress is erased before stdout is locked, which is the order in which lines that
+    /// are written by the slots take them.
+    pub fn write(&mut self, id: usize, output: Output) {
+        let _erased = self.screen.as_ref().map(|screen| screen.erase());
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        match output {
+            Output::Outcome(details, mut source) => {
+                if self.verbose {
+                    let _ = writeln!(
+                        stdout,
+                        "\nconcurr [INFO] Job {}: {} (attempt {})",
+                        id, details.execution.status, details.attempt
+                    );
+                }
+                let bytes = match self.results {
+                    Some(ref mut results) => match results.save(id, &details, &mut source) {
+                        Ok(bytes) => bytes,
+                        Err(why) => {
+                            eprintln!("concurr [CRITICAL]: unable to save job {}: {}", id, why);
+                            (0, 0)
+                        }
+                    },
+                    None => {
+                        let tag = self.tag.as_ref().map(|tag| {
+                            tag.expand(id, &details.input, details.node)
+                        });
+                        source.write(&mut stdout, tag.as_ref().map(String::as_str))
+                    }
+                };
+                let _ = stdout.flush();
+                let status = details.execution.status;
+                if status == Status::TimedOut {
+                    eprintln!("concurr [WARN]: job {} timed out", id);
+                }
+                if let Some(ref mut joblog) = self.joblog {
+                    joblog.record(id, &self.hosts[details.node], &details, bytes);
+                }
+                self.summary.record_status(status);
+            }
+            Output::Failed(node) => {
+                eprintln!("concurr [CRITICAL]: job {} could not be executed", id);
+                if let Some(ref mut joblog) = self.joblog {
+                    joblog.record_failed(id, &self.hosts[node]);
+                }
+                self.summary.record_unexecuted();
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use concurr::Seconds;
+use outputs::{Details, OutputSource};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The columns of the summary, which are separated by tabs.
+const HEADER: &str = "Seq\tExitval\tSignal\tJobRuntime\tDirectory";
+
+/// Stores the outputs of each job within a directory tree, rather than writing them to stdout.
+///
+/// Each job is given a directory that is named after its input values, such as `DIR/1/a/2/b`
+/// for a job whose first input source was `a`, and second was `b`. Within it, `stdout` and
+/// `stderr` contain the outputs of the job, `status` its exit status, and `seq` its ID.
+/// Additionally, `DIR/results.tsv` summarizes every job, with the directory of each relative
+/// to `DIR`.
+///
+/// Within the names of the directories, `%` and `/`, and control characters such as tabs and
+/// newlines, are written as `%` followed by their hexadecimal value. Values which would
+/// otherwise refer to an existing directory, `.` and `..`, have each `.` written as `%2E`, and
+/// empty values are written as `%`.
+pub struct Results {
+    dir:     PathBuf,
+    summary: BufWriter<File>,
+}
+
+impl Results {
+    /// When resuming, lines are appended to the summary of the previous run, if there is one.
+    pub fn create(dir: &Path, resume: bool) -> io::Result<Results> {
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(dir.join("results.tsv"))?;
+        let empty = file.metadata()?.len() == 0;
+        let mut summary = BufWriter::new(file);
+        if empty {
+            writeln!(summary, "{}", HEADER)?;
+        }
+        Ok(Results {
+            dir: dir.to_owned(),
+            summary,
+        })
+    }
+
+    /// Writes the outputs of the job to its directory, returning the number of bytes that were
+    /// written to its `stdout` and `stderr`.
+    pub fn save(
+        &mut self,
+        id: usize,
+        details: &Details,
+        source: &mut OutputSource,
+    ) -> io::Result<(u64, u64)> {
+        let relative = directory(&details.input);
+        let path = self.dir.join(&relative);
+        fs::create_dir_all(&path)?;
+
+        let mut stdout = File::create(path.join("stdout"))?;
+        let mut stderr = File::create(path.join("stderr"))?;
+        let bytes = source.copy(&mut stdout, &mut stderr)?;
+        writeln!(File::create(path.join("status"))?, "{}", details.execution.status)?;
+        writeln!(File::create(path.join("seq"))?, "{}", id)?;
+
+        let timing = &details.execution.timing;
+        writeln!(
+            self.summary,
+            "{}\t{}\t{}\t{}\t{}",
+            id,
+            details.execution.status,
+            timing.signal,
+            Seconds(timing.runtime),
+            relative.display()
+        )?;
+        self.summary.flush()?;
+        Ok(bytes)
+    }
+}
+
+/// The directory of a job, relative to the results directory.
+fn directory(input: &[String]) -> PathBuf {
+    let mut path = PathBuf::new();
+    for (source, value) in input.iter().enumerate() {
+        path.push((source + 1).to_string());
+        path.push(component(value));
+    }
+    path
+}
+
+fn component(value: &str) -> String {
+    match value {
+        "" => "%".to_owned(),
+        "." => "%2E".to_owned(),
+        ".." => "%2E%2E".to_owned(),
+        _ => {
+            let mut component = String::with_capacity(value.len());
+            for character in value.chars() {
+                if character == '%' || character == '/' || character.is_control() {
+                    for byte in character.to_string().bytes() {
+                        component.push_str(&format!("%{:02X}", byte));
+                    }
+                } else {
+                    component.push(character);
+                }
+            }
+            component
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::directory;
+    use std::path::Path;
+
+    #[test]
+    fn directories() {
+        let input = vec!["a/b.txt".to_owned(), "50%\tdone".to_owned()];
+        assert_eq!(directory(&input), Path::new("1/a%2Fb.txt/2/50%25%09done"));
+
+        let input = vec!["".to_owned(), ".".to_owned(), "..".to_owned()];
+        assert_eq!(directory(&input), Path::new("1/%/2/%2E/3/%2E%2E"));
+    }
+}
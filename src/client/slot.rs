@@ -1,71 +1,94 @@
-
 use super::{Inputs, Outputs};
 use certificate;
-use chashmap::CHashMap;
-use concurr::InsertJob;
-use connection::{attempt_connection, attempt_write};
-use std::collections::VecDeque;
+use concurr::{frame, Execution, Status, Stream, Timing};
+use connection::{attempt_connection, attempt_write, enable_streaming, upgrade};
+use inputs::Pending;
+use std::collections::HashMap;
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::str;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+
+/// The number of requests that a node processes at a time on each connection, beyond which it
+/// stops reading instructions until it has responded to them.
+pub const MAX_WINDOW: usize = 32;
 
+/// Submits inputs to a node over a single connection, keeping up to `window` inputs in flight
+/// at a time, and matching each result that is received to its input by the job ID.
 pub struct Slot<'a> {
     inputs:  Arc<Inputs>,
     outputs: Arc<Outputs>,
-    errors:  Arc<Mutex<VecDeque<(usize, String, u8)>>>,
-    failed:  Arc<CHashMap<usize, String>>,
-    kill:    Arc<AtomicBool>,
+    /// Identifies the node, so that jobs which failed on it may be retried elsewhere.
+    node:    usize,
     address: SocketAddr,
     id:      usize,
     domain:  &'a str,
+    window:  usize,
+    /// Whether the node supports length-prefixed frames, or only lines.
+    framed:  bool,
+    /// Whether the node sends the outputs of each job while it runs. Requires frames.
+    streams: bool,
+}
+
+/// An input that has been submitted to the node, whose result has not yet been received.
+struct InFlight {
+    pending: Pending,
+    sent:    SystemTime,
+    /// The outputs that have been streamed so far, unless they are written as lines.
+    stdout:  Vec<u8>,
+    stderr:  Vec<u8>,
+}
+
+/// The responses that the node may send for an input that was submitted.
+enum Response {
+    /// The input was processed. The timing and command line of the job are only sent by nodes
+    /// which support frames. If the outputs were streamed, they are empty.
+    Output(usize, Status, Vec<u8>, Vec<u8>, Option<(Timing, String)>),
+    /// A chunk of the outputs of a job that is still running.
+    Chunk(usize, Stream, Vec<u8>),
+    /// The node was unable to process the input.
+    Error(usize),
 }
 
 impl<'a> Slot<'a> {
     pub fn new(
         inputs: Arc<Inputs>,
         outputs: Arc<Outputs>,
-        errors: Arc<Mutex<VecDeque<(usize, String, u8)>>>,
-        failed: Arc<CHashMap<usize, String>>,
-        kill: Arc<AtomicBool>,
+        node: usize,
         address: SocketAddr,
         id: usize,
         domain: &'a str,
+        window: usize,
+        framed: bool,
+        streams: bool,
     ) -> Slot<'a> {
         Slot {
             inputs,
             outputs,
-            errors,
-            failed,
+            node,
             address,
             id,
-            kill,
             domain,
+            window,
+            framed,
+            streams,
         }
     }
 
-    fn next_input(&self) -> Option<(usize, String, u8)> {
-        match self.inputs.get_job() {
-            Some((jid, input)) => Some((jid, input, 0u8)),
-            None => {
-                let mut errors = self.errors.lock().unwrap();
-                errors.pop_back()
-            }
-        }
-    }
-
-    /// Listen for inputs, pass the inputs along, and store the outputs, serially.
+    /// Listen for inputs, pass the inputs along, and store the outputs.
     ///
-    /// This function contains the event loop that will run on each spawned slot, on each node.
-    /// All slots share access to the same `inputs` and `outputs` buffer. Inputs are popped
-    /// from the
-    /// `inputs` buffer, and their results are pushed onto the `outputs` buffer.
+    /// This function contains the event loop that will run for each node. All nodes share
+    /// access to the same `inputs` and `outputs` buffer. Inputs are popped from the `inputs`
+    /// buffer, and their results are pushed onto the `outputs` buffer.
     pub fn spawn(&self) {
+        // Inputs that have been submitted to the node, but whose results have not been received.
+        let mut in_flight = HashMap::new();
+
         loop {
             // Open a TCP stream to the node that will be used to submit inputs.
-            let stream = &mut match attempt_connection(
+            let stream = match attempt_connection(
                 self.address,
                 self.domain,
                 certificate::get(self.domain),
@@ -78,151 +101,293 @@ impl<'a> Slot<'a> {
                 }
             };
 
-            // A cache for eliminating heap allocations within the slot.
-            let mut cache = ResultsCache::new();
-
-            // Attempt to grab inputs from the inputs buffer until a kill signal is given.
-            while !self.kill.load(Ordering::Relaxed) {
-                // Grab an input from the shared inputs buffer.
-                let (jid, input, tries) = match self.next_input() {
-                    Some(input) => input,
-                    None => {
-                        thread::sleep(Duration::from_millis(1));
-                        continue;
+            // Switch the connection over to length-prefixed frames before submitting inputs,
+            // and have the outputs of each job sent while it runs.
+            let mut stream = stream;
+            let result = match (self.framed, self.streams) {
+                (true, true) => upgrade(&mut stream).and_then(|_| enable_streaming(&mut stream)),
+                (true, false) => upgrade(&mut stream),
+                _ => Ok(()),
+            };
+            if let Err(why) = result {
+                eprintln!("concurr [CRITICAL]: {:?}: {}", self.address, why);
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+
+            // Results are buffered for the lifetime of the connection, as several results may
+            // be received at once. Instructions are written to the inner stream.
+            let mut stream = BufReader::new(stream);
+
+            // If an error occured, the inputs that were in flight will be appended back to the
+            // input list for another attempt, and the connection will be reestablished.
+            match self.pipeline(&mut stream, &mut in_flight) {
+                Ok(()) => break,
+                Err(why) => {
+                    eprintln!("concurr [CRITICAL]: slot error: {}", why);
+                    for (_, job) in in_flight.drain() {
+                        self.retry(job.pending);
                     }
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    /// Submits inputs until the window is full, and then waits for a result before submitting
+    /// more. The slot will only park on the inputs buffer when no inputs are in flight.
+    fn pipeline<STREAM: Read + Write>(
+        &self,
+        stream: &mut BufReader<STREAM>,
+        in_flight: &mut HashMap<usize, InFlight>,
+    ) -> io::Result<()> {
+        // A cache for eliminating heap allocations within the slot.
+        let mut instruction = Vec::new();
+
+        loop {
+            while in_flight.len() < self.window {
+                let next = if in_flight.is_empty() {
+                    self.inputs.next(self.node)
+                } else {
+                    self.inputs.try_next(self.node)
                 };
 
-                // Generate the instruction that will be submitted based on the received input,
-                // and then write that instruction into the TcpStream.
-                let result = cache.write_instruction(stream, self.id, jid, &input)
-                    // Then wait for and return the results of the input, if possible.
-                    .and_then(|_| read_results(stream, &self.outputs, &mut cache));
+                let pending = match next {
+                    Some(next) => next,
+                    // No more inputs will be supplied, and all results have been received.
+                    None if in_flight.is_empty() => return Ok(()),
+                    None => break,
+                };
 
-                // Clear the cache so that the next input will have a clean slate.
-                cache.clear();
+                // Generate the instruction that will be submitted based on the received input,
+                // and then write that instruction into the stream.
+                let result = write_instruction(
+                    stream.get_mut(),
+                    &mut instruction,
+                    self.framed,
+                    (self.id, pending.id),
+                    &pending.input,
+                );
+                let job = InFlight {
+                    pending,
+                    sent: SystemTime::now(),
+                    stdout: Vec::new(),
+                    stderr: Vec::new(),
+                };
+                in_flight.insert(job.pending.id, job);
+                result?;
+            }
 
-                // If an error occured, append it back to the input list for another slot to
-                // attempt.
-                if let Err(why) = result {
-                    eprintln!("concurr [CRITICAL]: slot error: {}", why);
-                    if tries == 3 {
-                        self.failed.insert(jid, input);
-                    } else {
-                        let mut errors = self.errors.lock().unwrap();
-                        errors.push_back((jid, input, tries + 1));
+            // Then wait for the next result, and match it to the input that was submitted.
+            match read_results(stream, self.framed)? {
+                Response::Output(jid, status, stdout, stderr, details) => {
+                    if let Some(mut job) = in_flight.remove(&jid) {
+                        // Otherwise, the job is considered to have run from the moment that it
+                        // was submitted, to the moment that its result was received.
+                        let (timing, command) = details.unwrap_or_else(|| {
+                            let runtime = job.sent.elapsed().unwrap_or_default();
+                            let timing = Timing {
+                                start: job.sent,
+                                runtime,
+                                signal: 0,
+                            };
+                            (timing, String::new())
+                        });
+                        let execution = Execution {
+                            status,
+                            timing,
+                            command,
+                        };
+                        // The outputs of a streamed job which could not be sent as chunks
+                        // while it ran are sent along with its result.
+                        self.chunk(&mut job, jid, Stream::Stdout, &stdout);
+                        self.chunk(&mut job, jid, Stream::Stderr, &stderr);
+                        self.outputs
+                            .push_external(jid, self.node, execution, job.stdout, job.stderr);
                     }
-                    thread::sleep(Duration::from_secs(1));
                 }
+                Response::Chunk(jid, stream, chunk) => if let Some(job) = in_flight.get_mut(&jid) {
+                    self.chunk(job, jid, stream, &chunk);
+                },
+                Response::Error(jid) => if let Some(job) = in_flight.remove(&jid) {
+                    eprintln!("concurr [CRITICAL]: node was unable to process job {}", jid);
+                    self.retry(job.pending);
+                },
             }
-            break;
+        }
+    }
+
+    /// Writes the chunk of an output of a streamed job as lines, or otherwise buffers it until
+    /// the job has completed.
+    fn chunk(&self, job: &mut InFlight, jid: usize, stream: Stream, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        match self.outputs.lines {
+            Some(ref lines) if self.streams => {
+                lines.write(jid, self.node, stream, chunk, || self.inputs.input(jid))
+            }
+            _ => match stream {
+                Stream::Stdout => self.keep(&mut job.stdout, chunk),
+                Stream::Stderr => self.keep(&mut job.stderr, chunk),
+            },
+        }
+    }
+
+    /// Appends the outputs to the buffer, up to the limit. Nodes which support limits will not
+    /// send more than the limit allows, but older nodes send outputs in full.
+    fn keep(&self, buffer: &mut Vec<u8>, outputs: &[u8]) {
+        match self.outputs.limit {
+            Some(limit) => limit.append(buffer, outputs),
+            None => buffer.extend_from_slice(outputs),
+        }
+    }
+
+    /// Appends an input back to the input list for another attempt, unless it has already
+    /// been attempted too many times. The lines of the attempt which have yet to be written
+    /// are written first, as the outputs of every attempt are written in line mode.
+    fn retry(&self, pending: Pending) {
+        if let Some(ref lines) = self.outputs.lines {
+            lines.finish(pending.id);
+        }
+        if pending.tries == 3 {
+            self.outputs.push_failed(pending.id, self.node);
+        } else {
+            self.inputs.retry(pending, self.node);
         }
     }
 }
 
-/// Results obtained from an input always consist of precisely three lines. The status line, which
-/// contains the job ID and exit status; and the stdout and stderr lines, which have their newlines
-/// escaped.
+/// Results obtained from an input are received as a frame of three fields. The status field,
+/// which contains the job ID and status; and the stdout and stderr fields. Newer nodes follow
+/// these with the timing and command line of the job. If the node was unable to process the
+/// input, the status field contains an error instead. Nodes which stream outputs precede the
+/// result with frames of two fields, where the header contains the job ID followed by `out` or
+/// `err`, and the second field contains the chunk.
+///
+/// Nodes which do not support frames send precisely three lines instead, where newlines within
+/// the stdout and stderr lines are escaped, or a single error line.
 fn read_results<STREAM: Read>(
-    stream: &mut STREAM,
-    outputs: &Arc<Outputs>,
-    cache: &mut ResultsCache,
-) -> io::Result<()> {
-    let buffer = BufReader::new(stream);
+    stream: &mut BufReader<STREAM>,
+    framed: bool,
+) -> io::Result<Response> {
     // Read the results that were returned from the node.
-    cache.read_from(buffer)?;
-    // Attempt to parse the status line that was read.
-    let (id, status) = cache.parse_status()?;
-    // Push them onto the outputs buffer.
-    outputs.push_external(id, status, unescape(&cache.stdout), unescape(&cache.stderr));
-    Ok(())
-}
+    let mut fields = if framed { frame::read(stream)? } else { read_lines(stream)? }.into_iter();
+    let status = fields.next().ok_or_else(|| invalid("empty frame"))?;
+    let status = str::from_utf8(&status).map_err(|_| invalid("invalid status line"))?;
+
+    if status.starts_with("ERR ") {
+        let id = status[4..].split(' ').next().unwrap_or("");
+        return parse_usize(id).map(Response::Error);
+    }
 
-struct ResultsCache {
-    instruction: Vec<u8>,
-    status:      String,
-    stdout:      String,
-    stderr:      String,
+    if let Some((id, stream)) = parse_chunk(status) {
+        let chunk = fields.next().ok_or_else(|| invalid("missing chunk"))?;
+        return parse_usize(id).map(|id| Response::Chunk(id, stream, chunk));
+    }
+
+    // Attempt to parse the status line that was read.
+    let (id, status) = parse_status(status)?;
+    let stdout = fields.next().ok_or_else(|| invalid("missing stdout"))?;
+    let stderr = fields.next().ok_or_else(|| invalid("missing stderr"))?;
+    let details = match (fields.next(), fields.next()) {
+        (Some(timing), Some(command)) => {
+            let timing = str::from_utf8(&timing).ok().and_then(|timing| timing.parse().ok());
+            let timing = timing.ok_or_else(|| invalid("invalid timing"))?;
+            Some((timing, String::from_utf8_lossy(&command).into_owned()))
+        }
+        _ => None,
+    };
+    Ok(Response::Output(id, status, stdout, stderr, details))
 }
 
-impl ResultsCache {
-    pub fn new() -> ResultsCache {
-        ResultsCache {
-            instruction: Vec::new(),
-            status:      String::new(),
-            stdout:      String::new(),
-            stderr:      String::new(),
+/// Reads the status line, followed by the stdout and stderr lines unless the status line
+/// contains an error, and returns them as they would have been received in a frame.
+fn read_lines<STREAM: Read>(stream: &mut BufReader<STREAM>) -> io::Result<Vec<Vec<u8>>> {
+    let mut lines = Vec::with_capacity(3);
+    while lines.len() < 3 {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        // Remove the additional newline that was also recorded.
+        let _ = line.pop();
+        let error = lines.is_empty() && line.starts_with("ERR ");
+        lines.push(if lines.is_empty() { line.into_bytes() } else { unescape(&line).into_bytes() });
+        if error {
+            break;
         }
     }
+    Ok(lines)
+}
 
-    pub fn read_from<STREAM: Read>(
-        &mut self,
-        mut buffer: BufReader<&mut STREAM>,
-    ) -> io::Result<()> {
-        // The first line to read is the status line, containing the job ID and exit status.
-        let _ = buffer.read_line(&mut self.status)?;
-        // The second line contains the stdout stream.
-        let _ = buffer.read_line(&mut self.stdout)?;
-        // The third line contains the stderr stream.
-        let _ = buffer.read_line(&mut self.stderr)?;
-
-        // Remove the additional newlines that were also recorded.
-        let _ = self.status.pop();
-        let _ = self.stdout.pop();
-        let _ = self.stderr.pop();
-
-        Ok(())
+/// Writes an input to the node as a frame. The first field contains the command and job IDs,
+/// and is followed by one field for the value of each input source. Nodes which do not support
+/// frames only accept a single input value on each line, so the values are joined by spaces,
+/// as they would have been if the job had been executed locally.
+fn write_instruction<W: Write>(
+    stream: &mut W,
+    instruction: &mut Vec<u8>,
+    framed: bool,
+    (cid, jid): (usize, usize),
+    input: &[String],
+) -> io::Result<()> {
+    // Build the instruction
+    let header = ["inp ", &cid.to_string(), " ", &jid.to_string()].concat();
+    if framed {
+        let mut fields = Vec::with_capacity(input.len() + 1);
+        fields.push(header.as_bytes());
+        fields.extend(input.iter().map(|value| value.as_bytes()));
+        frame::encode(&fields, instruction)?;
+    } else {
+        instruction.extend_from_slice(header.as_bytes());
+        instruction.push(b' ');
+        for (index, value) in input.iter().enumerate() {
+            if index != 0 {
+                instruction.push(b' ');
+            }
+            instruction.extend_from_slice(value.as_bytes());
+        }
+        instruction.extend_from_slice(b"\r\n");
     }
 
-    pub fn write_instruction<W: Write>(
-        &mut self,
-        stream: &mut W,
-        cid: usize,
-        jid: usize,
-        input: &str,
-    ) -> io::Result<()> {
-        // Build the instruction
-        self.instruction.extend_from_slice(b"inp ");
-        self.instruction.extend_from_slice(&cid.to_string().as_bytes());
-        self.instruction.push(b' ');
-        self.instruction.extend_from_slice(&jid.to_string().as_bytes());
-        self.instruction.push(b' ');
-        self.instruction.extend_from_slice(input.as_bytes());
-        self.instruction.extend_from_slice(b"\r\n");
-
-        // Pass the instruction to the server. Attempt 3 times before failing.
-        attempt_write(stream, &self.instruction)?;
-
-        // Now clear the instruction
-        self.instruction.clear();
-        Ok(())
-    }
+    // Pass the instruction to the server. Attempt 3 times before failing.
+    let result = attempt_write(stream, &instruction);
 
-    pub fn parse_status(&self) -> io::Result<(usize, u8)> {
-        // Find the space, as we are going to split the results of the status line.
-        let pos = self.status
-            .find(' ')
-            .ok_or(io::Error::new(io::ErrorKind::Other, "invalid status line"))?;
-        // Split the status line in two, as there should be a whitespace to separate the results.
-        let (id, status) = self.status.split_at(pos);
-        // Then attempt to parse each value as their corresponding integer types.
-        Ok((parse_usize(id)?, parse_u8(&status[1..])?))
-    }
+    // Now clear the instruction
+    instruction.clear();
+    result
+}
 
-    pub fn clear(&mut self) {
-        self.status.clear();
-        self.stdout.clear();
-        self.stderr.clear();
-    }
+/// The status is either the exit status of the job, or `timeout` if the job was killed.
+fn parse_status(status: &str) -> io::Result<(usize, Status)> {
+    // Find the space, as we are going to split the results of the status line.
+    let pos = status.find(' ').ok_or_else(|| invalid("invalid status line"))?;
+    // Split the status line in two, as there should be a whitespace to separate the results.
+    let (id, status) = status.split_at(pos);
+    // Then attempt to parse each value as their corresponding integer types.
+    let status = status[1..].parse::<Status>().map_err(|_| invalid("invalid status"))?;
+    Ok((parse_usize(id)?, status))
 }
 
-fn parse_u8(input: &str) -> io::Result<u8> {
-    input.parse::<u8>().map_err(|_| io::Error::new(io::ErrorKind::Other, "ID is NaN"))
+/// Splits the job ID from the stream that a chunk was written to.
+fn parse_chunk(header: &str) -> Option<(&str, Stream)> {
+    let mut words = header.split(' ');
+    let id = words.next()?;
+    let stream = match words.next()? {
+        "out" => Stream::Stdout,
+        "err" => Stream::Stderr,
+        _ => return None,
+    };
+    Some((id, stream))
 }
 
 fn parse_usize(input: &str) -> io::Result<usize> {
-    input.parse::<usize>().map_err(|_| io::Error::new(io::ErrorKind::Other, "ID is NaN"))
+    input.parse::<usize>().map_err(|_| invalid("ID is NaN"))
 }
 
+fn invalid(message: &'static str) -> io::Error { io::Error::new(io::ErrorKind::Other, message) }
+
 fn unescape(input: &str) -> String {
     let mut start = 0;
     let mut string = String::with_capacity(input.len());
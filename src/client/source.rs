@@ -1,41 +1,265 @@
 use super::Inputs;
+use args::ArgUnit;
 use concurr::InsertJob;
+use permutate::Permutator;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 
-/// Reads inputs from a given file path
-pub fn file(inputs: &Arc<Inputs>, path: &Path, ninputs: &mut usize) {
-    match File::open(path) {
-        Ok(file) => read(file, inputs, ninputs),
-        Err(why) => {
-            eprintln!("concurr [CRITICAL]: unable to read inputs from '{:?}': {}", path, why);
+/// How the inputs within files and stdin are separated from one another, and which of them
+/// are skipped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Format {
+    pub delimiter: u8,
+    /// Whether whitespace is removed from either end of each input.
+    pub trim:      bool,
+    /// Whether inputs which begin with a `#` are skipped.
+    pub comments:  bool,
+}
+
+impl Default for Format {
+    /// Each line is an input, where blank lines and comments are skipped.
+    fn default() -> Format {
+        Format {
+            delimiter: b'\n',
+            trim:      true,
+            comments:  true,
+        }
+    }
+}
+
+/// A single byte which separates inputs, which may also be given as one of the escapes `\n`,
+/// `\t`, `\0`, or `\\`.
+pub struct Delimiter(pub u8);
+
+impl FromStr for Delimiter {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Delimiter, ()> {
+        match input {
+            "\\n" => Ok(Delimiter(b'\n')),
+            "\\t" => Ok(Delimiter(b'\t')),
+            "\\0" => Ok(Delimiter(0)),
+            "\\\\" => Ok(Delimiter(b'\\')),
+            _ if input.len() == 1 => Ok(Delimiter(input.as_bytes()[0])),
+            _ => Err(()),
         }
     }
 }
 
+/// Reads inputs from a given file path
+pub fn file(
+    inputs: &Arc<Inputs>,
+    path: &Path,
+    format: Format,
+    colsep: Option<&str>,
+    ninputs: &mut usize,
+) {
+    read_file(path, format, |input| {
+        inputs.insert_job(*ninputs, columns(input, colsep));
+        *ninputs += 1;
+    });
+}
+
 /// Reads inputs from standard input
-pub fn stdin(inputs: &Arc<Inputs>, ninputs: &mut usize) {
+pub fn stdin(inputs: &Arc<Inputs>, format: Format, colsep: Option<&str>, ninputs: &mut usize) {
     let stdin = io::stdin();
-    read(stdin.lock(), inputs, ninputs);
-}
-
-/// A generic function shared by both file-based and stdin-based inputs.
-fn read<F: Read>(input: F, inputs: &Arc<Inputs>, ninputs: &mut usize) {
-    for line in BufReader::new(input).lines() {
-        match line {
-            Ok(input) => {
-                let input = input.trim();
-                if !input.is_empty() && !input.starts_with('#') {
-                    inputs.insert_job(*ninputs, input.to_owned());
-                    *ninputs += 1;
+    read(stdin.lock(), format, |input| {
+        inputs.insert_job(*ninputs, columns(input, colsep));
+        *ninputs += 1;
+    });
+}
+
+/// If a column separator was given, each column of the input becomes a separate value.
+fn columns(input: String, colsep: Option<&str>) -> Vec<String> {
+    match colsep {
+        Some(colsep) => input.split(colsep).map(String::from).collect(),
+        None => vec![input],
+    }
+}
+
+/// Generates the permutations of every argument group supplied on the command line.
+///
+/// File groups are read into memory first, but the permutations themselves are generated
+/// lazily, as the slots drain the inputs buffer. If a column separator was given, the lines of
+/// file groups are split into columns, which are numbered after the values that precede them.
+pub fn permutations(
+    inputs: &Arc<Inputs>,
+    groups: Vec<ArgUnit>,
+    format: Format,
+    colsep: Option<&str>,
+    ninputs: &mut usize,
+) {
+    let files = groups
+        .iter()
+        .map(|group| match *group {
+            ArgUnit::Files(_) => colsep,
+            ArgUnit::Strings(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let sources = groups
+        .into_iter()
+        .map(|group| match group {
+            ArgUnit::Strings(strings) => strings,
+            ArgUnit::Files(paths) => {
+                let mut lines = Vec::new();
+                for path in &paths {
+                    read_file(Path::new(path), format, |input| lines.push(input));
                 }
+                lines
             }
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    for permutation in Permutator::new(&sources) {
+        let permutation = permutation
+            .into_iter()
+            .zip(files.iter())
+            .flat_map(|(value, &colsep)| columns(value, colsep))
+            .collect();
+        inputs.insert_job(*ninputs, permutation);
+        *ninputs += 1;
+    }
+}
+
+fn read_file<A: FnMut(String)>(path: &Path, format: Format, action: A) {
+    match File::open(path) {
+        Ok(file) => read(file, format, action),
+        Err(why) => {
+            eprintln!("concurr [CRITICAL]: unable to read inputs from '{:?}': {}", path, why);
+        }
+    }
+}
+
+/// A generic function shared by both file-based and stdin-based inputs. Empty inputs are
+/// always skipped, as are inputs which are not valid UTF-8, which are reported.
+fn read<F: Read, A: FnMut(String)>(input: F, format: Format, mut action: A) {
+    for (number, record) in BufReader::new(input).split(format.delimiter).enumerate() {
+        let mut record = match record {
+            Ok(record) => record,
             Err(why) => {
                 eprintln!("concurr [CRITICAL]: unable to read line from input: {}", why);
                 break;
             }
+        };
+
+        // Lines may also be terminated by a carriage return.
+        if format.delimiter == b'\n' && record.last() == Some(&b'\r') {
+            record.pop();
+        }
+
+        let record = match String::from_utf8(record) {
+            Ok(record) => record,
+            Err(_) => {
+                eprintln!("concurr [WARN]: skipping input {}, which is not UTF-8", number + 1);
+                continue;
+            }
+        };
+
+        let input = if format.trim { record.trim() } else { record.as_str() };
+        if !input.is_empty() && !(format.comments && input.starts_with('#')) {
+            action(input.to_owned());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{permutations, read, Delimiter, Format};
+    use args::ArgUnit;
+    use inputs::{Inputs, LOCAL};
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    fn inputs(input: &[u8], format: Format) -> Vec<String> {
+        let mut inputs = Vec::new();
+        read(input, format, |input| inputs.push(input));
+        inputs
+    }
+
+    #[test]
+    fn formats() {
+        let input = b" a \r\n#b\n\nc d\n";
+        assert_eq!(inputs(input, Format::default()), vec!["a", "c d"]);
+
+        let format = Format {
+            trim: false,
+            comments: false,
+            ..Format::default()
+        };
+        assert_eq!(inputs(input, format), vec![" a ", "#b", "c d"]);
+
+        let format = Format {
+            delimiter: "\\0".parse::<Delimiter>().unwrap().0,
+            trim: false,
+            ..Format::default()
+        };
+        assert_eq!(inputs(b" a\nb\0#c\0\0d", format), vec![" a\nb", "d"]);
+
+        // Inputs which are not UTF-8 are skipped, rather than ending the input.
+        assert_eq!(inputs(b"a\n\xFF\nb\n", Format::default()), vec!["a", "b"]);
+
+        assert_eq!(",".parse::<Delimiter>().unwrap().0, b',');
+        assert!("ab".parse::<Delimiter>().is_err());
+    }
+
+    #[test]
+    fn pending() {
+        let inputs = Arc::new(Inputs::new(0, false, HashSet::new(), 1, 4));
+        let finished = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let inputs = inputs.clone();
+            let finished = finished.clone();
+            thread::spawn(move || {
+                let values = (0..10).map(|value| value.to_string()).collect();
+                let groups = vec![ArgUnit::Strings(values)];
+                let mut total = 0;
+                permutations(&inputs, groups, Format::default(), None, &mut total);
+                finished.store(true, Ordering::SeqCst);
+                total
+            })
+        };
+
+        // The reader parks once the limit has been reached.
+        while inputs.inputs.parked() == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(inputs.inputs.len(), 4);
+        assert!(!finished.load(Ordering::SeqCst));
+
+        // And resumes as the inputs are taken, until every input has been read.
+        for id in 0..10 {
+            let pending = inputs.next(LOCAL).unwrap();
+            assert_eq!((pending.id, pending.input), (id, vec![id.to_string()]));
+        }
+        assert_eq!(reader.join().unwrap(), 10);
+        assert_eq!(inputs.inputs.len(), 0);
+    }
+
+    #[test]
+    fn columns() {
+        let path = env::temp_dir().join(format!("concurr-columns-{}", process::id()));
+        File::create(&path).unwrap().write_all(b"a,b\nc,d\n").unwrap();
+        let groups = vec![
+            ArgUnit::Strings(vec!["x,y".into()]),
+            ArgUnit::Files(vec![path.to_string_lossy().into_owned()]),
+        ];
+
+        // Only the lines of files are split into columns.
+        let inputs = Arc::new(Inputs::new(0, false, HashSet::new(), 1, 4));
+        let mut total = 0;
+        permutations(&inputs, groups, Format::default(), Some(","), &mut total);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(inputs.next(LOCAL).unwrap().input, vec!["x,y", "a", "b"]);
+        assert_eq!(inputs.next(LOCAL).unwrap().input, vec!["x,y", "c", "d"]);
+    }
+}
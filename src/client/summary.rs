@@ -0,0 +1,122 @@
+use concurr::Status;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// Like GNU Parallel, the number of failed jobs that may be reported by the exit status is
+/// capped, so that it will not be confused with the exit status of an error.
+pub const MAX_FAILED: usize = 101;
+/// Returned when a job could not be executed or transmitted, or the client could not start.
+pub const ERROR_STATUS: i32 = 255;
+
+/// Determines how the exit status of the client is derived from the exit statuses of its jobs.
+/// Regardless of the policy, jobs which could not be executed result in `ERROR_STATUS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitPolicy {
+    /// The number of jobs which returned a non-zero exit status or timed out, up to `MAX_FAILED`.
+    Count,
+    /// `1` if any job returned a non-zero exit status or timed out.
+    Any,
+    /// `0`, regardless of the exit status of each job.
+    Ignore,
+}
+
+impl FromStr for ExitPolicy {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<ExitPolicy, ()> {
+        match input {
+            "count" => Ok(ExitPolicy::Count),
+            "any" => Ok(ExitPolicy::Any),
+            "ignore" => Ok(ExitPolicy::Ignore),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Tallies the outcome of each job, for reporting once every job has completed.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub succeeded:  usize,
+    /// Jobs which were executed, but returned a non-zero exit status.
+    pub nonzero:    usize,
+    /// Jobs which were killed, as they did not exit before their deadline.
+    pub timed_out:  usize,
+    /// Jobs which could not be executed, or whose results could not be received from a node.
+    pub unexecuted: usize,
+}
+
+impl Summary {
+    pub fn record_status(&mut self, status: Status) {
+        match status {
+            Status::Exited(0) => self.succeeded += 1,
+            Status::Exited(_) => self.nonzero += 1,
+            Status::TimedOut => self.timed_out += 1,
+        }
+    }
+
+    pub fn record_unexecuted(&mut self) { self.unexecuted += 1; }
+
+    pub fn total(&self) -> usize { self.succeeded + self.failures() }
+
+    /// The number of jobs which did not succeed.
+    pub fn failures(&self) -> usize { self.nonzero + self.timed_out + self.unexecuted }
+
+    /// Whether any job did not succeed.
+    pub fn failed(&self) -> bool { self.failures() != 0 }
+
+    pub fn exit_status(&self, policy: ExitPolicy) -> i32 {
+        if self.unexecuted != 0 {
+            return ERROR_STATUS;
+        }
+
+        let failed = self.nonzero + self.timed_out;
+        match policy {
+            ExitPolicy::Count => failed.min(MAX_FAILED) as i32,
+            ExitPolicy::Any if failed != 0 => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} jobs: {} succeeded", self.total(), self.succeeded)?;
+        if self.nonzero != 0 {
+            write!(f, ", {} returned a non-zero exit status", self.nonzero)?;
+        }
+        if self.timed_out != 0 {
+            write!(f, ", {} timed out", self.timed_out)?;
+        }
+        if self.unexecuted != 0 {
+            write!(f, ", {} could not be executed", self.unexecuted)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExitPolicy, Summary, ERROR_STATUS, MAX_FAILED};
+    use concurr::Status;
+
+    #[test]
+    fn exit_status() {
+        let mut summary = Summary::default();
+        summary.record_status(Status::Exited(0));
+        assert_eq!(summary.exit_status(ExitPolicy::Count), 0);
+
+        (0..199).for_each(|_| summary.record_status(Status::Exited(3)));
+        summary.record_status(Status::TimedOut);
+        assert_eq!(summary.exit_status(ExitPolicy::Count), MAX_FAILED as i32);
+        assert_eq!(summary.exit_status(ExitPolicy::Any), 1);
+        assert_eq!(summary.exit_status(ExitPolicy::Ignore), 0);
+        assert_eq!(
+            summary.to_string(),
+            "201 jobs: 1 succeeded, 199 returned a non-zero exit status, 1 timed out"
+        );
+
+        summary.record_unexecuted();
+        assert_eq!(summary.exit_status(ExitPolicy::Ignore), ERROR_STATUS);
+        assert!(summary.to_string().ends_with(", 1 could not be executed"));
+    }
+}
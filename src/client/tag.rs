@@ -0,0 +1,85 @@
+use concurr::{expand, Tokens};
+use std::io::{self, Write};
+
+/// Prefixes each line of the outputs of a job with a template that is expanded for that job,
+/// so that it may be told which job, or which node, wrote each line.
+#[derive(Clone)]
+pub struct Tag {
+    template: Tokens,
+    /// The name of each node, by its ID, for `{host}`.
+    hosts:    Vec<String>,
+}
+
+impl Tag {
+    pub fn new(template: Tokens, hosts: Vec<String>) -> Tag { Tag { template, hosts } }
+
+    /// The prefix of each line of the job, which separates the tag from the line with a tab.
+    pub fn expand(&self, id: usize, input: &[String], node: usize) -> String {
+        let mut tag = expand(&self.template, id, input, &self.hosts[node]);
+        tag.push('\t');
+        tag
+    }
+}
+
+/// Writes the tag before each line that is written to the inner writer. The number of bytes
+/// that are reported as written excludes the tags.
+pub struct Tagged<'a, W: Write> {
+    inner: W,
+    tag:   &'a str,
+    /// Whether the next byte begins a new line.
+    start: bool,
+}
+
+impl<'a, W: Write> Tagged<'a, W> {
+    pub fn new(inner: W, tag: &'a str) -> Tagged<'a, W> {
+        Tagged {
+            inner,
+            tag,
+            start: true,
+        }
+    }
+}
+
+impl<'a, W: Write> Write for Tagged<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if self.start {
+                self.inner.write_all(self.tag.as_bytes())?;
+            }
+            let end = match rest.iter().position(|&byte| byte == b'\n') {
+                Some(pos) => pos + 1,
+                None => rest.len(),
+            };
+            self.inner.write_all(&rest[..end])?;
+            self.start = rest[end - 1] == b'\n';
+            rest = &rest[end..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tag, Tagged};
+    use concurr::Tokens;
+    use std::io::Write;
+
+    #[test]
+    fn tagging() {
+        let hosts = vec![":".to_owned(), "node".to_owned()];
+        let tag = Tag::new(Tokens::template("{host} {#}"), hosts);
+        let tag = tag.expand(3, &["a".to_owned()], 1);
+        assert_eq!(tag, "node 3\t");
+
+        let mut output = Vec::new();
+        {
+            let mut tagged = Tagged::new(&mut output, &tag);
+            tagged.write_all(b"a\nb").unwrap();
+            tagged.write_all(b"c\n\n").unwrap();
+        }
+        assert_eq!(output, b"node 3\ta\nnode 3\tbc\nnode 3\t\n".to_vec());
+    }
+}
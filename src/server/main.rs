@@ -1,7 +1,5 @@
 extern crate app_dirs;
 extern crate bytes;
-extern crate chashmap;
-extern crate coco;
 extern crate concurr;
 extern crate futures;
 extern crate libc;
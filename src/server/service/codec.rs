@@ -1,38 +1,83 @@
-use super::{JobEvent, ResponseEvent};
+use super::{stream_name, JobEvent, ResponseEvent};
 use bytes::BytesMut;
+use concurr::frame;
 use std::io;
 use tokio_io::codec::{Decoder, Encoder};
+use tokio_proto::streaming::multiplex::{Frame, RequestId};
 
-pub struct ConcurrCodec;
+/// The length of the longest instruction that is accepted, whether as a line or a frame.
+/// Instructions only carry commands and inputs, so a client which sends more is rejected,
+/// rather than having its instruction buffered.
+const MAX_INSTRUCTION: usize = 16 << 20;
+
+/// Decodes instructions and encodes responses as lines, until the client requests to upgrade
+/// the connection with `set framed`. Every message after that point is a length-prefixed frame.
+///
+/// Each instruction is given the next request ID, which is only used to match the response to
+/// the request. Responses are matched to their inputs by the client with their job IDs.
+#[derive(Default)]
+pub struct ConcurrCodec {
+    /// Set once the upgrade instruction has been decoded.
+    read_frames:  bool,
+    /// Set once the response to the upgrade instruction has been encoded.
+    write_frames: bool,
+    next_id:      RequestId,
+}
 
 impl Decoder for ConcurrCodec {
-    type Item = JobEvent;
+    type Item = Frame<JobEvent, (), io::Error>;
     type Error = io::Error;
 
-    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<JobEvent>> {
-        if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        let event = self.decode_event(buf)?;
+        Ok(event.map(|message| {
+            self.next_id += 1;
+            Frame::Message {
+                id: self.next_id,
+                message,
+                body: false,
+                solo: false,
+            }
+        }))
+    }
+}
+
+impl ConcurrCodec {
+    fn decode_event(&mut self, buf: &mut BytesMut) -> io::Result<Option<JobEvent>> {
+        if self.read_frames {
+            let (fields, consumed) = match frame::decode(&buf[..], MAX_INSTRUCTION)? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
             // remove the serialized frame from the buffer.
-            let argument = buf.split_to(i - 1);
-            // Also remove the '\r\n'
-            buf.split_to(2);
-
-            if argument.len() < 5 {
-                Err(io::Error::new(io::ErrorKind::Other, "invalid call"))
-            } else {
-                // Match the corresponding instruction to it's event.
-                match &argument[..3] {
-                    // Signals to create a command.
-                    b"com" => JobEvent::get_command(&argument[4..]),
-                    // Signals to process an input.
-                    b"inp" => JobEvent::get_input(&argument[4..]),
-                    // Signals to obtain some information about the server.
-                    b"get" => JobEvent::get_option(&argument[4..]),
-                    // Signals to remove a job from the command pool.
-                    b"del" => JobEvent::del_command(&argument[4..]),
-                    // The client has sent an invalid instruction.
-                    _ => Err(io::Error::new(io::ErrorKind::Other, "invalid instruction")),
+            buf.split_to(consumed);
+
+            // The first field is the instruction, and the remaining fields are its values.
+            return match fields.split_first() {
+                Some((header, values)) if header.starts_with(b"inp ") => {
+                    JobEvent::get_framed_input(&header[4..], values)
                 }
+                Some((header, _)) => parse_instruction(header),
+                None => Err(io::Error::new(io::ErrorKind::Other, "empty frame")),
+            };
+        }
+
+        if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+            // remove the serialized frame from the buffer, along with the '\n'.
+            let mut argument = buf.split_to(i + 1);
+            argument.truncate(i);
+            // Also remove the '\r', if the line was terminated with '\r\n'.
+            if argument.last() == Some(&b'\r') {
+                argument.truncate(i - 1);
             }
+
+            let event = parse_instruction(&argument)?;
+            if event == Some(JobEvent::Upgrade) {
+                self.read_frames = true;
+            }
+            Ok(event)
+        } else if buf.len() > MAX_INSTRUCTION {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "instruction is too long"))
         } else {
             Ok(None)
         }
@@ -40,12 +85,250 @@ impl Decoder for ConcurrCodec {
 }
 
 impl Encoder for ConcurrCodec {
-    type Item = ResponseEvent;
+    type Item = Frame<ResponseEvent, ResponseEvent, io::Error>;
     type Error = io::Error;
 
-    fn encode(&mut self, msg: ResponseEvent, buf: &mut BytesMut) -> io::Result<()> {
-        buf.extend(msg.to_string().as_bytes());
-        buf.extend(b"\n");
+    /// The outputs of a job that is streamed are the body of its response, so nothing is sent
+    /// for the message itself, nor for the end of the body.
+    fn encode(&mut self, frame: Self::Item, buf: &mut BytesMut) -> io::Result<()> {
+        match frame {
+            Frame::Message { message, body, .. } => if !body {
+                self.encode_event(message, buf)?;
+            },
+            Frame::Body { chunk: Some(chunk), .. } => self.encode_event(chunk, buf)?,
+            Frame::Body { chunk: None, .. } => (),
+            Frame::Error { error, .. } => return Err(error),
+        }
+        Ok(())
+    }
+}
+
+impl ConcurrCodec {
+    fn encode_event(&mut self, msg: ResponseEvent, buf: &mut BytesMut) -> io::Result<()> {
+        if !self.write_frames {
+            buf.extend(msg.to_string().as_bytes());
+            buf.extend(b"\n");
+            if let ResponseEvent::Upgrade = msg {
+                self.write_frames = true;
+            }
+            return Ok(());
+        }
+
+        let mut frame = Vec::new();
+        match msg {
+            // The timing and command line of the job follow the outputs, where clients that are
+            // unaware of them will ignore them.
+            ResponseEvent::Output(jid, execution, stdout, stderr) => {
+                let header = [jid.to_string(), execution.status.to_string()].join(" ");
+                let timing = execution.timing.to_string();
+                let command = execution.command.as_bytes();
+                frame::encode(
+                    &[header.as_bytes(), &stdout, &stderr, timing.as_bytes(), command],
+                    &mut frame,
+                )?;
+            }
+            ResponseEvent::Chunk(jid, stream, chunk) => {
+                let header = [&jid.to_string(), " ", stream_name(stream)].concat();
+                frame::encode(&[header.as_bytes(), &chunk], &mut frame)?;
+            }
+            ResponseEvent::Error(jid, input) => {
+                let header = ["ERR ", &jid.to_string()].concat();
+                frame::encode(&[header.as_bytes(), input.as_bytes()], &mut frame)?;
+            }
+            msg => frame::encode(&[msg.to_string()], &mut frame)?,
+        }
+        buf.extend(&frame);
         Ok(())
     }
 }
+
+/// Match the corresponding instruction to it's event.
+fn parse_instruction(argument: &[u8]) -> io::Result<Option<JobEvent>> {
+    if argument.len() < 5 {
+        return Err(io::Error::new(io::ErrorKind::Other, "invalid call"));
+    }
+
+    // Signals the beginning of the `hello` exchange.
+    if argument.starts_with(b"hello ") {
+        return JobEvent::get_hello(argument);
+    }
+
+    match &argument[..3] {
+        // Signals to create a command.
+        b"com" => JobEvent::get_command(&argument[4..]),
+        // Signals to process an input.
+        b"inp" => JobEvent::get_input(&argument[4..]),
+        // Signals to obtain some information about the server.
+        b"get" => JobEvent::get_option(&argument[4..]),
+        // Signals to change how the connection is encoded.
+        b"set" => JobEvent::set_option(&argument[4..]),
+        // Signals to remove a job from the command pool.
+        b"del" => JobEvent::del_command(&argument[4..]),
+        // The client has sent an invalid instruction.
+        _ => Err(io::Error::new(io::ErrorKind::Other, "invalid instruction")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConcurrCodec, JobEvent, ResponseEvent, MAX_INSTRUCTION};
+    use bytes::BytesMut;
+    use concurr::{frame, Execution, Status, Stream, Timing};
+    use concurr::protocol::{self, Hello};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tokio_io::codec::{Decoder, Encoder};
+    use tokio_proto::streaming::multiplex::Frame;
+
+    fn execution(status: Status) -> Execution {
+        Execution {
+            status,
+            timing: Timing {
+                start:   UNIX_EPOCH + Duration::from_secs(1),
+                runtime: Duration::from_millis(20),
+                signal:  0,
+            },
+            command: "echo 'a b'".into(),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let mut codec = ConcurrCodec::default();
+        let mut buf = BytesMut::from(&b"\nget cores\r\nget comms\nhello 3 exec foo\n"[..]);
+        assert!(codec.decode_event(&mut buf).is_err());
+        assert_eq!(codec.decode_event(&mut buf).unwrap(), Some(JobEvent::GetCores));
+        assert_eq!(codec.decode_event(&mut buf).unwrap(), Some(JobEvent::GetCommands));
+        assert_eq!(
+            codec.decode_event(&mut buf).unwrap(),
+            Some(JobEvent::Hello(Hello {
+                version:  3,
+                features: protocol::EXEC,
+            }))
+        );
+        assert_eq!(codec.decode_event(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn frames() {
+        let binary = (0..256).map(|byte| byte as u8).collect::<Vec<u8>>();
+        let mut codec = ConcurrCodec::default();
+
+        // Instructions that follow the upgrade are decoded from frames.
+        let mut buf = BytesMut::from(&b"set framed\r\n"[..]);
+        let mut input = Vec::new();
+        frame::encode(&[&b"inp +priority=2 0 7"[..], b"a\nb\x1F", b""], &mut input).unwrap();
+        buf.extend(&input[..input.len() - 1]);
+        assert_eq!(codec.decode_event(&mut buf).unwrap(), Some(JobEvent::Upgrade));
+        assert_eq!(codec.decode_event(&mut buf).unwrap(), None);
+        buf.extend(&input[input.len() - 1..]);
+        assert_eq!(
+            codec.decode_event(&mut buf).unwrap(),
+            Some(JobEvent::Input(0, 7, vec!["a\nb\x1F".into(), "".into()], 2))
+        );
+        assert!(buf.is_empty());
+
+        // Responses that follow the upgrade are encoded as frames, with the output untouched.
+        let mut buf = BytesMut::new();
+        codec.encode_event(ResponseEvent::Upgrade, &mut buf).unwrap();
+        let output = execution(Status::Exited(1));
+        let output = ResponseEvent::Output(7, output, binary.clone(), b"\n".to_vec());
+        codec.encode_event(output, &mut buf).unwrap();
+        codec.encode_event(ResponseEvent::Error(8, "a b".into()), &mut buf).unwrap();
+        let output = ResponseEvent::Output(9, execution(Status::TimedOut), Vec::new(), Vec::new());
+        codec.encode_event(output, &mut buf).unwrap();
+        assert_eq!(&buf[..10], b"OK framed\n");
+
+        let mut reader = &buf[10..];
+        assert_eq!(
+            frame::read(&mut reader).unwrap(),
+            vec![
+                b"7 1".to_vec(),
+                binary,
+                b"\n".to_vec(),
+                b"1.000 0.020 0".to_vec(),
+                b"echo 'a b'".to_vec(),
+            ]
+        );
+        assert_eq!(
+            frame::read(&mut reader).unwrap(),
+            vec![b"ERR 8".to_vec(), b"a b".to_vec()]
+        );
+        assert_eq!(
+            frame::read(&mut reader).unwrap(),
+            vec![
+                b"9 timeout".to_vec(),
+                Vec::new(),
+                Vec::new(),
+                b"1.000 0.020 0".to_vec(),
+                b"echo 'a b'".to_vec(),
+            ]
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn limits() {
+        // Lines which exceed the maximum are rejected before their end has been received.
+        let mut codec = ConcurrCodec::default();
+        let mut buf = BytesMut::from(&b"inp 0 1 "[..]);
+        buf.extend(&vec![b'a'; MAX_INSTRUCTION]);
+        assert!(codec.decode_event(&mut buf).is_err());
+
+        // As are frames, once their length has been received.
+        let mut codec = ConcurrCodec::default();
+        let mut buf = BytesMut::from(&b"set framed\n"[..]);
+        assert_eq!(codec.decode_event(&mut buf).unwrap(), Some(JobEvent::Upgrade));
+        let length = MAX_INSTRUCTION + 1;
+        for shift in &[24, 16, 8, 0] {
+            buf.extend(&[(length >> shift) as u8]);
+        }
+        assert!(codec.decode_event(&mut buf).is_err());
+    }
+
+    #[test]
+    fn streaming() {
+        let mut codec = ConcurrCodec {
+            write_frames: true,
+            ..ConcurrCodec::default()
+        };
+
+        // Each request is given the next request ID.
+        let mut buf = BytesMut::from(&b"get cores\nset streaming\n"[..]);
+        match codec.decode(&mut buf).unwrap() {
+            Some(Frame::Message { id: 1, message: JobEvent::GetCores, .. }) => (),
+            _ => panic!("expected the first request"),
+        }
+        match codec.decode(&mut buf).unwrap() {
+            Some(Frame::Message { id: 2, message: JobEvent::Stream, .. }) => (),
+            _ => panic!("expected the second request"),
+        }
+
+        // Only the chunks and the output of a job that is streamed are sent.
+        let mut buf = BytesMut::new();
+        let frames = vec![
+            Frame::Message {
+                id:      3,
+                message: ResponseEvent::Streamed,
+                body:    true,
+                solo:    false,
+            },
+            Frame::Body {
+                id:    3,
+                chunk: Some(ResponseEvent::Chunk(4, Stream::Stderr, b"a\n".to_vec())),
+            },
+            Frame::Body {
+                id:    3,
+                chunk: Some(ResponseEvent::Output(4, execution(Status::Exited(0)), vec![], vec![])),
+            },
+            Frame::Body { id: 3, chunk: None },
+        ];
+        for frame in frames {
+            codec.encode(frame, &mut buf).unwrap();
+        }
+
+        let mut reader = &buf[..];
+        assert_eq!(frame::read(&mut reader).unwrap(), vec![b"4 err".to_vec(), b"a\n".to_vec()]);
+        assert_eq!(frame::read(&mut reader).unwrap()[0], b"4 0".to_vec());
+        assert!(reader.is_empty());
+    }
+}
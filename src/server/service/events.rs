@@ -1,27 +1,63 @@
 use super::obtain;
+use concurr::{Execution, Limit, Mode, Stream, Timeout};
+use concurr::protocol::Hello;
 use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::str;
 
 #[derive(Debug, PartialEq)]
 pub enum JobEvent {
-    /// Create a new command to store in the job server.
-    Command(String),
+    /// Create a new command to store in the job server, how it should be executed, how long
+    /// each of its jobs may run for, and how many bytes of each of their outputs are kept.
+    Command(String, Mode, Option<Timeout>, Option<Limit>),
     /// Execute an input, using the ID of the command to execute it with, and the ID of the job
-    /// being executed.
-    Input(usize, usize, String),
+    /// being executed. The input contains one value per input source, and is followed by the
+    /// priority of the input.
+    Input(usize, usize, Vec<String>, u8),
     /// Return a list of commands currently stored in the job server.
     GetCommands,
     /// Return the number of cores on the machine that the job server is running on.
     GetCores,
-    /// Delete a command from the command list.
-    StopJob(usize),
+    /// Delete a command from the command list, and whether its running jobs should be killed.
+    StopJob(usize, bool),
+    /// Switch the connection over to length-prefixed frames.
+    Upgrade,
+    /// Stream the outputs of the jobs that are submitted on this connection while they run.
+    Stream,
+    /// The client has announced its protocol version and features.
+    Hello(Hello),
 }
 
 impl JobEvent {
     /// Obtain the `Command` event from the input.
-    pub fn get_command(input: &[u8]) -> io::Result<Option<JobEvent>> {
-        Ok(Some(JobEvent::Command(obtain(input)?)))
+    ///
+    /// The command may be preceded by options, which each begin with a `+`, such as `+exec`,
+    /// or `+timeout=N`, where `N` is a number of seconds or a percentage of the median runtime,
+    /// or `+max-output=N`, where `N` is a number of bytes.
+    pub fn get_command(mut input: &[u8]) -> io::Result<Option<JobEvent>> {
+        let mut mode = Mode::Shell;
+        let mut timeout = None;
+        let mut limit = None;
+        while let Some((option, remainder)) = next_option(input) {
+            match option {
+                b"exec" => mode = Mode::Exec,
+                _ if option.starts_with(b"timeout=") => {
+                    let value = obtain(&option[8..])?;
+                    timeout = Some(value.parse::<Timeout>().map_err(|_| {
+                        io::Error::new(io::ErrorKind::Other, "invalid timeout")
+                    })?);
+                }
+                _ if option.starts_with(b"max-output=") => {
+                    let value = obtain(&option[11..])?;
+                    limit = Some(value.parse::<Limit>().map_err(|_| {
+                        io::Error::new(io::ErrorKind::Other, "invalid output limit")
+                    })?);
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "unsupported option")),
+            }
+            input = remainder;
+        }
+        Ok(Some(JobEvent::Command(obtain(input)?, mode, timeout, limit)))
     }
 
     /// Parses the input and returns one of the `Get` variants.
@@ -33,30 +69,98 @@ impl JobEvent {
         }
     }
 
+    /// Parses the protocol version and features that the client has announced.
+    pub fn get_hello(input: &[u8]) -> io::Result<Option<JobEvent>> {
+        Hello::parse(&obtain(input)?)
+            .map(|hello| Some(JobEvent::Hello(hello)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "invalid hello"))
+    }
+
+    /// Parses the input and returns the upgrade that was requested by the client.
+    pub fn set_option(input: &[u8]) -> io::Result<Option<JobEvent>> {
+        match input {
+            b"framed" => Ok(Some(JobEvent::Upgrade)),
+            b"streaming" => Ok(Some(JobEvent::Stream)),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "unsupported value")),
+        }
+    }
+
     /// Attempts to parse a number from the input and uses that as the job to stop.
-    pub fn del_command(input: &[u8]) -> io::Result<Option<JobEvent>> {
-        Ok(Some(JobEvent::StopJob(parse_usize(input)?)))
+    ///
+    /// The number may be preceded by the `+kill` option, which kills the jobs that are running,
+    /// rather than waiting for them to complete.
+    pub fn del_command(mut input: &[u8]) -> io::Result<Option<JobEvent>> {
+        let mut kill = false;
+        while let Some((option, remainder)) = next_option(input) {
+            match option {
+                b"kill" => kill = true,
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "unsupported option")),
+            }
+            input = remainder;
+        }
+        Ok(Some(JobEvent::StopJob(parse_usize(input)?, kill)))
     }
 
     /// Attempts to parse the `Input` event from a given byte slice.
+    ///
+    /// The input may be preceded by a `+priority=N` option, where inputs with a higher
+    /// priority will be processed before the inputs which are already queued.
     pub fn get_input(input: &[u8]) -> io::Result<Option<JobEvent>> {
-        // Find the first space to get the value of the command ID to execute.
-        if let Some(index) = input.iter().position(|&b| b == b' ') {
-            // Obtain the ID of the command to execute.
-            let cid = parse_usize(&input[..index])?;
-            // Adjust the region of the slice for future searching.
-            let input = &input[index + 1..];
-            // Find the first space to get the value of the job ID to execute.
-            if let Some(index) = input.iter().position(|&b| b == b' ') {
-                // Obtain the ID of the job to execute.
-                let id = parse_usize(&input[..index])?;
-                // Then return an `Input` event that contains the input to process.
-                return Ok(Some(JobEvent::Input(cid, id, obtain(&input[index + 1..])?)));
+        match input_header(input)? {
+            (cid, id, priority, Some(input)) => {
+                // Lines carry a single value, as only frames have a field for each input source.
+                Ok(Some(JobEvent::Input(cid, id, vec![obtain(input)?], priority)))
             }
+            // Indicates that the supplied input didn't provide enough arguments
+            _ => Err(io::Error::new(io::ErrorKind::Other, "not enough arguments")),
         }
+    }
+
+    /// Parses the `Input` event from a frame, where the value of each input source is stored
+    /// within its own field, following the header.
+    pub fn get_framed_input(header: &[u8], values: &[Vec<u8>]) -> io::Result<Option<JobEvent>> {
+        match input_header(header)? {
+            (cid, id, priority, None) => {
+                let values = values
+                    .iter()
+                    .map(|value| obtain(value))
+                    .collect::<io::Result<Vec<String>>>()?;
+                Ok(Some(JobEvent::Input(cid, id, values, priority)))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::Other, "values must be sent as fields")),
+        }
+    }
+}
 
-        // Indicates that the supplied input didn't provide enough arguments
-        Err(io::Error::new(io::ErrorKind::Other, "not enough arguments"))
+/// Parses the options, command ID, and job ID of an input, followed by the remainder of the
+/// input, if the job ID was followed by a space.
+fn input_header(mut input: &[u8]) -> io::Result<(usize, usize, u8, Option<&[u8]>)> {
+    let mut priority = 0;
+    while let Some((option, remainder)) = next_option(input) {
+        if option.starts_with(b"priority=") {
+            priority = parse_u8(&option[9..])?;
+        } else {
+            return Err(io::Error::new(io::ErrorKind::Other, "unsupported option"));
+        }
+        input = remainder;
+    }
+
+    // Find the first space to get the value of the command ID to execute.
+    let index = input
+        .iter()
+        .position(|&b| b == b' ')
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "not enough arguments"))?;
+    // Obtain the ID of the command to execute.
+    let cid = parse_usize(&input[..index])?;
+    // Adjust the region of the slice for future searching.
+    let input = &input[index + 1..];
+    // The job ID may be followed by a space and the values of the input.
+    match input.iter().position(|&b| b == b' ') {
+        Some(index) => {
+            let id = parse_usize(&input[..index])?;
+            Ok((cid, id, priority, Some(&input[index + 1..])))
+        }
+        None => Ok((cid, parse_usize(input)?, priority, None)),
     }
 }
 
@@ -65,25 +169,74 @@ pub enum ResponseEvent {
     /// Returned when an issue occurs when processing an input.
     Error(usize, String),
     /// Returned when an input has successfully complete. The first value is the job ID,
-    /// followed
-    /// by the exit status of the executed job, standard output, and standard error.
-    Output(usize, u8, String, String),
+    /// followed by a description of the executed job, standard output, and standard error.
+    Output(usize, Execution, Vec<u8>, Vec<u8>),
+    /// Precedes the outputs of a job that are streamed, which are sent as the body of the
+    /// response, rather than as the response itself. Nothing is sent for this response.
+    Streamed,
+    /// A chunk of the output of a job that is streamed, which was written to the given stream.
+    /// Once the job has completed, its `Output` follows, without the outputs already sent.
+    Chunk(usize, Stream, Vec<u8>),
     /// Contains a single line of space-deliminated information.
     Info(String),
+    /// Acknowledges that the connection will be switched over to length-prefixed frames.
+    Upgrade,
+    /// Announces the protocol version and features of the server.
+    Hello(Hello),
 }
 
 impl Display for ResponseEvent {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             ResponseEvent::Error(id, ref out) => write!(f, "ERR {} {}", id, escape(out)),
-            ResponseEvent::Output(jid, status, ref stdout, ref stderr) => {
-                write!(f, "{} {}\n{}\n{}", jid, status, escape(stdout), escape(stderr))
-            }
+            // Lines may only contain text, so invalid UTF-8 will be replaced.
+            ResponseEvent::Output(jid, ref execution, ref stdout, ref stderr) => write!(
+                f,
+                "{} {}\n{}\n{}",
+                jid,
+                execution.status,
+                escape(&String::from_utf8_lossy(stdout)),
+                escape(&String::from_utf8_lossy(stderr))
+            ),
+            ResponseEvent::Streamed => Ok(()),
+            ResponseEvent::Chunk(jid, stream, ref chunk) => write!(
+                f,
+                "{} {} {}",
+                jid,
+                stream_name(stream),
+                escape(&String::from_utf8_lossy(chunk))
+            ),
             ResponseEvent::Info(ref info) => write!(f, "{}", info),
+            ResponseEvent::Upgrade => f.write_str("OK framed"),
+            ResponseEvent::Hello(ref hello) => write!(f, "{}", hello),
         }
     }
 }
 
+/// Chunks of output are identified by the stream that they were written to.
+pub fn stream_name(stream: Stream) -> &'static str {
+    match stream {
+        Stream::Stdout => "out",
+        Stream::Stderr => "err",
+    }
+}
+
+/// Splits the next option, which begins with a `+`, from the remainder of the input.
+fn next_option(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    if input.first() != Some(&b'+') {
+        return None;
+    }
+    let end = input.iter().position(|&b| b == b' ').unwrap_or(input.len());
+    Some((&input[1..end], &input[(end + 1).min(input.len())..]))
+}
+
+fn parse_u8(input: &[u8]) -> io::Result<u8> {
+    str::from_utf8(&input)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid UTF-8"))?
+        .parse::<u8>()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "value is NaN"))
+}
+
 fn parse_usize(input: &[u8]) -> io::Result<usize> {
     str::from_utf8(&input)
         .map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid UTF-8"))?
@@ -92,8 +245,9 @@ fn parse_usize(input: &[u8]) -> io::Result<usize> {
 }
 
 /// Newlines are disallowed in the encoded messages, and therefore this will replace newlines
-/// with a corresponding escape sequence. The client will be responsible for unescaping the
-/// output of this function.
+/// with a corresponding escape sequence. Backslashes are also escaped, so that they will not be
+/// mistaken for an escape sequence. The client will be responsible for unescaping the output of
+/// this function.
 fn escape(input: &str) -> String {
     let mut start = 0;
     let mut output = String::with_capacity(input.len());
@@ -106,7 +260,8 @@ fn escape(input: &str) -> String {
             }
             '\\' => {
                 output.push_str(&input[start..id]);
-                start = id;
+                output.push_str("\\\\");
+                start = id + 1;
             }
             _ => (),
         }
@@ -116,3 +271,41 @@ fn escape(input: &str) -> String {
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, JobEvent, Limit, Mode, Timeout};
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("a\nb\\n\\"), "a\\nb\\\\n\\\\");
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            JobEvent::get_command(b"+exec +timeout=200% ls -l").unwrap(),
+            Some(JobEvent::Command("ls -l".into(), Mode::Exec, Some(Timeout::Percent(200)), None))
+        );
+        assert_eq!(
+            JobEvent::get_command(b"+max-output=1024 ls").unwrap(),
+            Some(JobEvent::Command("ls".into(), Mode::Shell, None, Some(Limit(1024))))
+        );
+        assert!(JobEvent::get_command(b"+timeout=soon ls").is_err());
+        assert!(JobEvent::get_command(b"+max-output=lots ls").is_err());
+    }
+
+    #[test]
+    fn inputs() {
+        assert_eq!(
+            JobEvent::get_input(b"+priority=3 0 1 a b\x1Fc").unwrap(),
+            Some(JobEvent::Input(0, 1, vec!["a b\x1Fc".into()], 3))
+        );
+        assert_eq!(
+            JobEvent::get_framed_input(b"0 1", &[b"a\r\n".to_vec(), b"\x1F".to_vec()]).unwrap(),
+            Some(JobEvent::Input(0, 1, vec!["a\r\n".into(), "\x1F".into()], 0))
+        );
+        assert!(JobEvent::get_input(b"0 1").is_err());
+        assert!(JobEvent::get_framed_input(b"0 1 a", &[]).is_err());
+    }
+}
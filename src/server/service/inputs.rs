@@ -1,12 +1,19 @@
-use coco::Stack;
-use concurr::InsertJob;
+use concurr::{InsertJob, Queue};
 
+/// Inputs are processed in the order that they were received, unless they were given a
+/// priority, in which case they will be processed before all inputs of a lower priority.
 pub struct Inputs {
-    pub stack: Stack<(usize, String)>,
+    pub queue: Queue<(usize, Vec<String>)>,
+}
+
+impl Inputs {
+    pub fn insert_priority(&self, id: usize, job: Vec<String>, priority: u8) {
+        self.queue.push_priority((id, job), priority);
+    }
 }
 
 impl InsertJob for Inputs {
-    fn get_job(&self) -> Option<(usize, String)> { self.stack.pop() }
+    fn get_job(&self) -> Option<(usize, Vec<String>)> { self.queue.pop() }
 
-    fn insert_job(&self, id: usize, job: String) { self.stack.push((id, job)); }
+    fn insert_job(&self, id: usize, job: Vec<String>) { self.queue.push_back((id, job)); }
 }
@@ -9,18 +9,20 @@ pub use self::events::*;
 pub use self::proto::*;
 
 use self::inputs::Inputs;
-use self::outputs::Outputs;
-use chashmap::CHashMap;
-use coco::Stack;
-use concurr::{slot_event, InsertJob, Job, Tokens};
+use self::outputs::{Outputs, CHUNKS};
+use concurr::{slot_event, Deadline, Job, Queue, Running, Tokens};
+use concurr::protocol::Hello;
 use futures::{future, Future};
+use futures::sync::{mpsc, oneshot};
 use num_cpus;
-use std::io::{self, Read};
+use std::cell::Cell;
+use std::io;
 use std::str;
 use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
+use tokio_proto::streaming::{Body, Message};
 use tokio_service::Service;
 
 fn obtain(input: &[u8]) -> io::Result<String> {
@@ -29,20 +31,28 @@ fn obtain(input: &[u8]) -> io::Result<String> {
         .map_err(|_| io::Error::new(io::ErrorKind::Other, "invalid UTF-8"))
 }
 
-
 type Jobs = Arc<RwLock<Vec<Option<Job<Inputs, Outputs>>>>>;
 
+/// Each connection is served by its own `Concurr`, which shares the commands of every
+/// connection.
 pub struct Concurr {
-    commands: Jobs,
+    commands:  Jobs,
+    /// Whether the outputs of the jobs that are submitted on this connection are streamed.
+    streaming: Cell<bool>,
 }
 
 impl Concurr {
-    pub fn new(commands: Jobs) -> Concurr { Concurr { commands } }
+    pub fn new(commands: Jobs) -> Concurr {
+        Concurr {
+            commands,
+            streaming: Cell::new(false),
+        }
+    }
 }
 
 impl Service for Concurr {
-    type Request = JobEvent;
-    type Response = ResponseEvent;
+    type Request = Message<JobEvent, Body<(), io::Error>>;
+    type Response = Message<ResponseEvent, Body<ResponseEvent, io::Error>>;
 
     // For non-streaming protocols, service errors are always io::Error
     type Error = io::Error;
@@ -52,24 +62,24 @@ impl Service for Concurr {
 
     // Produce a future for computing a response from a request.
     fn call(&self, req: Self::Request) -> Self::Future {
-        let event = match req {
-            JobEvent::Command(cmd) => {
+        let event = match req.into_inner() {
+            JobEvent::Command(cmd, mode, timeout, limit) => {
                 // Contains the tokenized expression of the command that will be shared
                 // with each slot attached to the command.
-                let command = Tokens::new(&cmd);
+                let command = Tokens::with_mode(&cmd, mode);
 
                 // This will store the inputs that each slot will concurrently grab inputs from.
                 let inputs = Arc::new(Inputs {
-                    stack: Stack::new(),
+                    queue: Queue::new(),
                 });
                 // While this will store the results of each complete job.
-                let outputs = Arc::new(Outputs {
-                    outputs: CHashMap::new(),
-                });
-                // This will be used to notify threads that it's time to stop.
-                let kill = Arc::new(AtomicBool::new(false));
+                let outputs = Arc::new(Outputs::new(limit));
                 // And this will be used to determine when all threads have stopped.
                 let parked = Arc::new(AtomicUsize::new(0));
+                // While this will be used to kill the jobs that are running.
+                let running = Arc::new(Running::new());
+                // And this will be used to kill the jobs that run for too long.
+                let deadline = timeout.map(|timeout| Arc::new(Deadline::new(timeout)));
 
                 // We shall create as many slots as there are cores in the system.
                 let slots = num_cpus::get();
@@ -78,11 +88,12 @@ impl Service for Concurr {
                     let inputs = inputs.clone();
                     let outputs = outputs.clone();
                     let command = command.clone();
-                    let kill = kill.clone();
                     let parked = parked.clone();
-                    let _ = thread::spawn(
-                        move || slot_event(slot, command, inputs, outputs, kill, parked),
-                    );
+                    let running = running.clone();
+                    let deadline = deadline.clone();
+                    let _ = thread::spawn(move || {
+                        slot_event(slot, command, inputs, outputs, parked, running, deadline)
+                    });
                 }
 
                 // Store the command in the command pool, and obtain the ID of the command.
@@ -96,12 +107,14 @@ impl Service for Concurr {
                             command,
                             inputs,
                             outputs,
-                            kill,
                             parked,
+                            running,
+                            deadline,
                         });
 
                         // The ID is the index where we just stored the command.
-                        return Box::new(future::ok(ResponseEvent::Info(id.to_string())));
+                        let response = ResponseEvent::Info(id.to_string());
+                        return Box::new(future::ok(Message::WithoutBody(response)));
                     }
                     id += 1;
                 }
@@ -113,43 +126,59 @@ impl Service for Concurr {
                     command,
                     inputs,
                     outputs,
-                    kill,
                     parked,
+                    running,
+                    deadline,
                 }));
 
                 // And the indice where the command is stored is the ID to return.
                 ResponseEvent::Info(id.to_string())
             }
-            JobEvent::Input(cid, jid, input) => {
+            JobEvent::Input(cid, jid, input, priority) => {
                 let commands = self.commands.read().unwrap();
                 match commands.get(cid) {
+                    Some(&Some(ref unit)) if self.streaming.get() => {
+                        // The outputs will be sent by the slot as the body of the response.
+                        let (sender, body) = mpsc::channel(CHUNKS);
+                        unit.outputs.register_streamed(jid, sender, input.join(" "));
+                        unit.inputs.insert_priority(jid, input, priority);
+                        let response = Message::WithBody(ResponseEvent::Streamed, Body::from(body));
+                        return Box::new(future::ok(response));
+                    }
                     Some(&Some(ref unit)) => {
-                        unit.inputs.insert_job(jid, input.clone());
-                        let result = unit.outputs.remove(&jid);
-
-                        match result {
-                            Some((status, mut stdout, mut stderr)) => {
-                                let mut outbuf = String::new();
-                                let mut errbuf = String::new();
-                                let _ = stdout.read_to_string(&mut outbuf);
-                                let _ = stderr.read_to_string(&mut errbuf);
-                                drop(stdout);
-                                drop(stderr);
-                                return Box::new(
-                                    future::ok(ResponseEvent::Output(jid, status, outbuf, errbuf)),
-                                );
+                        // Register for the result before submitting the input, so that the
+                        // result will not be missed by a slot that finishes quickly.
+                        let result = unit.outputs.register(jid);
+                        unit.inputs.insert_priority(jid, input.clone(), priority);
+
+                        // The response will be sent once a slot has published the result.
+                        let response = result.then(move |result| match result {
+                            Ok(Some((execution, stdout, stderr))) => {
+                                Ok(ResponseEvent::Output(jid, execution, stdout, stderr))
                             }
-                            None => {
+                            Ok(None) => {
                                 eprintln!("[CRITICAL] job {} errored with a critical issue", cid);
+                                Ok(ResponseEvent::Error(jid, input.join(" ")))
                             }
-                        }
+                            Err(_) => {
+                                eprintln!("[WARN] command {} was deleted before job {}", cid, jid);
+                                Ok(ResponseEvent::Error(jid, input.join(" ")))
+                            }
+                        });
+                        return Box::new(response.map(Message::WithoutBody));
                     }
                     _ => eprintln!("[WARN] command ID {} not found", cid),
                 }
 
-                ResponseEvent::Error(jid, input)
+                ResponseEvent::Error(jid, input.join(" "))
             }
             JobEvent::GetCores => ResponseEvent::Info(num_cpus::get().to_string()),
+            JobEvent::Upgrade => ResponseEvent::Upgrade,
+            JobEvent::Stream => {
+                self.streaming.set(true);
+                ResponseEvent::Info("OK streaming".into())
+            }
+            JobEvent::Hello(_) => ResponseEvent::Hello(Hello::new()),
             JobEvent::GetCommands => {
                 let commands = self.commands.read().unwrap();
                 let mut output;
@@ -162,9 +191,8 @@ impl Service for Concurr {
                             break;
                         }
                         None => {
-                            return Box::new(
-                                future::ok(ResponseEvent::Info("no jobs available".into())),
-                            )
+                            let response = ResponseEvent::Info("no jobs available".into());
+                            return Box::new(future::ok(Message::WithoutBody(response)));
                         }
                         _ => (),
                     }
@@ -178,27 +206,38 @@ impl Service for Concurr {
 
                 ResponseEvent::Info(output)
             }
-            JobEvent::StopJob(id) => {
-                let mut commands = self.commands.write().unwrap();
-                // Obtain the corresponding job from the given ID.
-                if let Some(command) = commands.get_mut(id) {
-                    // We shall signal the threads to quite, and then set this command to None.
-                    if let Some(ref unit) = *command {
-                        eprintln!("[INFO] removing job {}", id);
+            JobEvent::StopJob(id, kill) => {
+                // Take the command from the command list, so that its ID may be reused. The
+                // lock is not held while its slots are stopped.
+                let unit = self.commands.write().unwrap().get_mut(id).and_then(Option::take);
+                if let Some(unit) = unit {
+                    eprintln!("[INFO] removing job {}", id);
+                    // The slots are stopped on another thread, as slots which are streaming
+                    // outputs rely on this reactor to send them. The response is sent once
+                    // every slot has parked.
+                    let (sender, receiver) = oneshot::channel();
+                    thread::spawn(move || {
                         // Signal to the threads that it's time to come home.
-                        unit.kill.store(true, Ordering::Relaxed);
-                        // Wait for them to park before resetting the command.
-                        while unit.parked.load(Ordering::Relaxed) != unit.slots {
+                        unit.inputs.queue.close();
+                        // Kill the jobs that are running, if requested, rather than waiting.
+                        if kill {
+                            unit.running.kill_all();
+                        }
+                        while unit.parked.load(Ordering::SeqCst) != unit.slots {
                             thread::sleep(Duration::from_millis(1));
                         }
-                    }
-                    // Reset the command
-                    *command = None;
+                        let _ = sender.send(());
+                    });
+                    let response = receiver.then(|_| {
+                        let response = ResponseEvent::Info("deleted job".into());
+                        Ok(Message::WithoutBody(response))
+                    });
+                    return Box::new(response);
                 }
                 ResponseEvent::Info("deleted job".into())
             }
         };
 
-        Box::new(future::ok(event))
+        Box::new(future::ok(Message::WithoutBody(event)))
     }
 }
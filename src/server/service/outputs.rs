@@ -1,30 +1,352 @@
-use chashmap::CHashMap;
-use concurr::InsertOutput;
+use super::ResponseEvent;
+use concurr::{anonymous_file, Execution, InsertOutput, Limit, Stream};
+use futures::sync::mpsc;
+use futures::sync::oneshot::{self, Receiver, Sender};
+use futures::{Future, Sink};
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+
+/// A description of the executed job, its standard output, and standard error, or `None` if the
+/// job could not be executed.
+pub type JobResult = Option<(Execution, Vec<u8>, Vec<u8>)>;
+
+/// Sends the chunks of output of a job that is streamed, followed by its output.
+pub type ChunkSender = mpsc::Sender<io::Result<ResponseEvent>>;
+
+/// The number of chunks of a streamed job which may be waiting to be sent to the client.
+pub const CHUNKS: usize = 16;
+
+/// The number of bytes of each output of a streamed job which may be held in memory, and thus
+/// the largest chunk that is sent. Outputs beyond this are spilled to a file until there is
+/// room to send them.
+const HELD: usize = 1 << 20;
+
+/// How the outputs of a job are sent to the client that submitted it.
+enum Registration {
+    /// The outputs are buffered while the job runs, and sent once it has completed.
+    Complete(Sender<JobResult>, Vec<u8>, Vec<u8>),
+    /// The outputs are sent while the job is running, followed by the output of the job.
+    Streamed(Streamed),
+}
+
+struct Streamed {
+    sender: ChunkSender,
+    /// The input of the job, in case it could not be executed.
+    input:  String,
+    /// The number of bytes that the job has written to stdout and stderr.
+    seen:   (u64, u64),
+    /// The outputs which could not be sent yet, because the client is not keeping up.
+    held:   (Held, Held),
+}
+
+impl Streamed {
+    /// Attempts to send the outputs that are held, without blocking. Outputs which do not fit
+    /// in the channel are held until the next chunk arrives, or the job completes. Returns
+    /// `false` if the client has disconnected.
+    fn flush(&mut self, id: usize) -> bool {
+        for &stream in &[Stream::Stdout, Stream::Stderr] {
+            let held = match stream {
+                Stream::Stdout => &mut self.held.0,
+                Stream::Stderr => &mut self.held.1,
+            };
+            while !held.is_empty() {
+                let chunk = ResponseEvent::Chunk(id, stream, held.take());
+                if let Err(why) = self.sender.try_send(Ok(chunk)) {
+                    if !why.is_full() {
+                        return false;
+                    }
+                    if let Ok(ResponseEvent::Chunk(_, _, chunk)) = why.into_inner() {
+                        held.restore(chunk);
+                    }
+                    return true;
+                }
+            }
+        }
+        true
+    }
+
+    /// Sends the final response of the job, after the outputs that are still held. A clone of
+    /// the sender is always able to send one message, so if nothing is held, this will not
+    /// block. Otherwise, the held outputs are sent as chunks from another thread, which waits
+    /// for the client to make room for them, so that the slot is not held up.
+    fn complete(self, id: usize, event: ResponseEvent) {
+        let Streamed { sender, held: (mut stdout, mut stderr), .. } = self;
+        if stdout.is_empty() && stderr.is_empty() {
+            let _ = sender.clone().try_send(Ok(event));
+            return;
+        }
+
+        thread::spawn(move || {
+            let mut sender = sender;
+            for &mut (stream, ref mut held) in
+                &mut [(Stream::Stdout, &mut stdout), (Stream::Stderr, &mut stderr)]
+            {
+                while !held.is_empty() {
+                    let chunk = ResponseEvent::Chunk(id, stream, held.take());
+                    sender = match sender.send(Ok(chunk)).wait() {
+                        Ok(sender) => sender,
+                        Err(_) => return,
+                    };
+                }
+            }
+            let _ = sender.send(Ok(event)).wait();
+        });
+    }
+}
+
+/// The output of a stream which has yet to be sent. Up to `HELD` bytes are kept in memory, and
+/// those which follow are appended to an anonymous file, which is read back as they are sent.
+#[derive(Default)]
+struct Held {
+    memory:  Vec<u8>,
+    file:    Option<File>,
+    /// The number of bytes of the file which have been read back, and written to it.
+    read:    u64,
+    written: u64,
+}
+
+impl Held {
+    fn is_empty(&self) -> bool { self.memory.is_empty() && !self.spilled() }
+
+    fn spilled(&self) -> bool { self.written > self.read }
+
+    /// Appends the bytes to the memory, until it is full, and spills the remainder.
+    fn extend(&mut self, bytes: &[u8]) {
+        let room = if self.spilled() { 0 } else { HELD.saturating_sub(self.memory.len()) };
+        let (kept, spilled) = bytes.split_at(room.min(bytes.len()));
+        self.memory.extend_from_slice(kept);
+        if !spilled.is_empty() {
+            if let Err(why) = self.spill(spilled) {
+                eprintln!("[WARN] discarding {} bytes of output: {}", spilled.len(), why);
+            }
+        }
+    }
+
+    fn spill(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(anonymous_file()?);
+        }
+        let mut file = self.file.as_ref().unwrap();
+        file.seek(SeekFrom::Start(self.written)).and_then(|_| file.write_all(bytes))?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Takes the bytes at the front of the output, which are no more than `HELD` bytes.
+    fn take(&mut self) -> Vec<u8> {
+        if self.memory.is_empty() && self.spilled() {
+            if let Err(why) = self.refill() {
+                let discarded = self.written - self.read;
+                eprintln!("[WARN] discarding {} bytes of output: {}", discarded, why);
+                self.read = self.written;
+            }
+            if !self.spilled() {
+                // Every spilled byte has been read back, so the file may be reused.
+                self.read = 0;
+                self.written = 0;
+                let _ = self.file.as_ref().map(|file| file.set_len(0));
+            }
+        }
+        mem::replace(&mut self.memory, Vec::new())
+    }
+
+    /// Returns a chunk that could not be sent to the front of the output. This is only called
+    /// after `take`, which leaves the memory empty.
+    fn restore(&mut self, chunk: Vec<u8>) { self.memory = chunk; }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let length = (self.written - self.read).min(HELD as u64) as usize;
+        self.memory.resize(length, 0);
+        let memory = &mut self.memory;
+        let mut file = self.file.as_ref().expect("spilled output without a spill file");
+        file.seek(SeekFrom::Start(self.read)).and_then(|_| file.read_exact(memory))?;
+        self.read += length as u64;
+        Ok(())
+    }
+}
 
 pub struct Outputs {
-    pub outputs: CHashMap<usize, Option<(u8, File, File)>>,
+    outputs: Mutex<HashMap<usize, Registration>>,
+    /// The number of bytes of each output of a job that are kept.
+    limit:   Option<Limit>,
 }
 
 impl Outputs {
-    pub fn remove(&self, id: &usize) -> Option<(u8, File, File)> {
-        loop {
-            match self.outputs.remove(id) {
-                Some(element) => {
-                    return element;
+    pub fn new(limit: Option<Limit>) -> Outputs {
+        Outputs {
+            outputs: Mutex::new(HashMap::new()),
+            limit,
+        }
+    }
+
+    /// Registers interest in the result of the given job. The returned future resolves once
+    /// the slot that processes the job has inserted its result, so that the caller need not
+    /// block while the job is running.
+    pub fn register(&self, id: usize) -> Receiver<JobResult> {
+        let (sender, receiver) = oneshot::channel();
+        let registration = Registration::Complete(sender, Vec::new(), Vec::new());
+        self.outputs.lock().unwrap().insert(id, registration);
+        receiver
+    }
+
+    /// Registers the given job to have its outputs sent through the sender while it runs.
+    pub fn register_streamed(&self, id: usize, sender: ChunkSender, input: String) {
+        let registration = Registration::Streamed(Streamed {
+            sender,
+            input,
+            seen: (0, 0),
+            held: Default::default(),
+        });
+        self.outputs.lock().unwrap().insert(id, registration);
+    }
+}
+
+/// The outputs of every job are read by the slot's thread while the job runs, so that a job
+/// will not block once it has filled its pipes. Once the job has exited, they have been read
+/// to the end.
+impl InsertOutput for Outputs {
+    fn insert(&self, id: usize, result: Option<(Execution, File, File)>) {
+        let registration = self.outputs.lock().unwrap().remove(&id);
+        match registration {
+            Some(Registration::Complete(sender, stdout, stderr)) => {
+                let _ = sender.send(result.map(|(execution, ..)| (execution, stdout, stderr)));
+            }
+            Some(Registration::Streamed(job)) => {
+                let event = match result {
+                    Some((execution, ..)) => {
+                        ResponseEvent::Output(id, execution, Vec::new(), Vec::new())
+                    }
+                    None => {
+                        eprintln!("[CRITICAL] job {} errored with a critical issue", id);
+                        ResponseEvent::Error(id, job.input.clone())
+                    }
+                };
+                job.complete(id, event);
+            }
+            None => (),
+        }
+    }
+
+    fn streams(&self, _id: usize) -> bool { true }
+
+    /// Chunks of jobs that are streamed are sent to the connection without blocking the slot,
+    /// which would otherwise be held up by a client that does not keep up. The outputs which
+    /// cannot be sent yet are held, and sent once there is room, or along with the output of
+    /// the job. If the client has disconnected, the remaining outputs of the job are discarded,
+    /// as are the outputs which exceed the limit.
+    fn insert_chunk(&self, id: usize, stream: Stream, chunk: &[u8]) {
+        let mut outputs = self.outputs.lock().unwrap();
+        let connected = match outputs.get_mut(&id) {
+            Some(&mut Registration::Complete(_, ref mut stdout, ref mut stderr)) => {
+                let buffer = match stream {
+                    Stream::Stdout => stdout,
+                    Stream::Stderr => stderr,
+                };
+                match self.limit {
+                    Some(limit) => limit.append(buffer, chunk),
+                    None => buffer.extend_from_slice(chunk),
                 }
-                None => {
-                    thread::sleep(Duration::from_millis(1));
+                return;
+            }
+            Some(&mut Registration::Streamed(ref mut job)) => {
+                {
+                    let (seen, held) = match stream {
+                        Stream::Stdout => (&mut job.seen.0, &mut job.held.0),
+                        Stream::Stderr => (&mut job.seen.1, &mut job.held.1),
+                    };
+                    match self.limit {
+                        Some(limit) => held.extend(&limit.keep(seen, chunk)),
+                        None => held.extend(chunk),
+                    }
                 }
+                job.flush(id)
             }
+            None => return,
+        };
+
+        if !connected {
+            outputs.remove(&id);
         }
     }
 }
 
-impl InsertOutput for Outputs {
-    fn insert(&self, id: usize, result: Option<(u8, File, File)>) {
-        self.outputs.insert(id, result);
+impl Drop for Outputs {
+    /// Jobs which are still registered once their command has been deleted will never be
+    /// executed. The responses of those that are streamed must therefore be sent here, whereas
+    /// the futures of the others resolve with an error.
+    fn drop(&mut self) {
+        for (id, registration) in self.outputs.lock().unwrap().drain() {
+            if let Registration::Streamed(job) = registration {
+                eprintln!("[WARN] command was deleted before job {}", id);
+                let input = job.input.clone();
+                job.complete(id, ResponseEvent::Error(id, input));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Held, Streamed, CHUNKS, HELD};
+    use super::super::ResponseEvent;
+    use concurr::Stream;
+    use futures::sync::mpsc;
+    use futures::Stream as FutureStream;
+
+    fn output(length: usize) -> Vec<u8> { (0..length).map(|byte| byte as u8).collect() }
+
+    #[test]
+    fn held() {
+        let mut held = Held::default();
+        let output = output(HELD + 10);
+        held.extend(&output[..10]);
+        held.extend(&output[10..]);
+        assert!(held.spilled());
+
+        let chunk = held.take();
+        assert_eq!(chunk, &output[..HELD]);
+        held.restore(chunk);
+        assert_eq!(held.take(), &output[..HELD]);
+        assert_eq!(held.take(), &output[HELD..]);
+        assert!(held.is_empty());
+
+        // The file is reused once it has been read back.
+        held.extend(&output);
+        assert_eq!(held.take(), &output[..HELD]);
+        assert_eq!(held.take(), &output[HELD..]);
+        assert!(held.is_empty() && held.take().is_empty());
+    }
+
+    #[test]
+    fn complete() {
+        let (sender, receiver) = mpsc::channel(CHUNKS);
+        let output = output(2 * HELD + 1);
+        let mut job = Streamed {
+            sender,
+            input: String::new(),
+            seen: (0, 0),
+            held: Default::default(),
+        };
+        job.held.1.extend(&output);
+        job.complete(3, ResponseEvent::Error(3, "input".into()));
+
+        let mut events = receiver.wait().map(|event| event.unwrap().unwrap());
+        for range in &[0..HELD, HELD..2 * HELD, 2 * HELD..output.len()] {
+            match events.next() {
+                Some(ResponseEvent::Chunk(3, Stream::Stderr, chunk)) => {
+                    assert_eq!(chunk, &output[range.clone()])
+                }
+                _ => panic!("expected a chunk of stderr"),
+            }
+        }
+        match events.next() {
+            Some(ResponseEvent::Error(3, input)) => assert_eq!(input, "input"),
+            _ => panic!("expected the response to follow the chunks"),
+        }
+        assert!(events.next().is_none());
     }
 }
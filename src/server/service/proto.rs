@@ -2,20 +2,33 @@ use super::{ConcurrCodec, JobEvent, ResponseEvent};
 use std::io;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::codec::Framed;
-use tokio_proto::pipeline::ServerProto;
+use tokio_proto::streaming::multiplex::ServerProto;
 
+/// Responses are multiplexed, so that the response to an input may be sent as soon as its job
+/// has completed, and may be streamed while the job is running, regardless of the inputs that
+/// were received before it.
 pub struct ConcurrProto;
 
 impl<T: AsyncRead + AsyncWrite + 'static> ServerProto<T> for ConcurrProto {
-    /// For this protocol style, `Request` matches the `Item` type of the codec's `Decoder`
+    /// Each instruction that is decoded by the codec is the message of a request.
     type Request = JobEvent;
 
-    /// For this protocol style, `Response` matches the `Item` type of the codec's `Encoder`
+    /// Instructions never have a body.
+    type RequestBody = ();
+
+    /// Each response is encoded by the codec, unless its outputs are streamed as its body.
     type Response = ResponseEvent;
 
+    /// The chunks of output of a job that is streamed, followed by its output.
+    type ResponseBody = ResponseEvent;
+
+    type Error = io::Error;
+
     /// A bit of boilerplate to hook in the codec:
     type Transport = Framed<T, ConcurrCodec>;
     type BindTransport = Result<Self::Transport, io::Error>;
 
-    fn bind_transport(&self, io: T) -> Self::BindTransport { Ok(io.framed(ConcurrCodec)) }
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        Ok(io.framed(ConcurrCodec::default()))
+    }
 }
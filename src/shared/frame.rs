@@ -0,0 +1,115 @@
+use std::io::{self, Read};
+
+// # Framing
+//
+// Once a connection has been upgraded with the `set framed` instruction, every message is sent
+// as a frame. Each frame begins with the length of its payload, as a 32-bit big-endian integer.
+// The payload is a sequence of fields, each of which is likewise prefixed with its length, and
+// may contain arbitrary bytes. The first field is always the header of the message, which is
+// written as it would be in the line protocol, such as `inp 0 1` or `1 0`.
+
+/// Appends a frame containing each of the given fields to the buffer. Frames which are too long
+/// for their length to be written as a 32-bit integer are rejected, and nothing is appended.
+pub fn encode<FIELD: AsRef<[u8]>>(fields: &[FIELD], buffer: &mut Vec<u8>) -> io::Result<()> {
+    let length = fields.iter().map(|field| 4 + field.as_ref().len()).sum::<usize>();
+    if length > u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame is too long to encode"));
+    }
+    write_length(buffer, length);
+    for field in fields {
+        write_length(buffer, field.as_ref().len());
+        buffer.extend_from_slice(field.as_ref());
+    }
+    Ok(())
+}
+
+/// Decodes the fields of the frame at the beginning of the buffer, returning the fields and the
+/// number of bytes that were consumed. If the frame has not been received in full, `None` is
+/// returned. Frames with payloads longer than `max` are rejected as soon as their length has
+/// been received, so that they are never buffered.
+pub fn decode(buffer: &[u8], max: usize) -> io::Result<Option<(Vec<Vec<u8>>, usize)>> {
+    if buffer.len() < 4 {
+        return Ok(None);
+    }
+
+    let length = read_length(buffer);
+    if length > max {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame exceeds the maximum length"));
+    }
+    if buffer.len() < 4 + length {
+        return Ok(None);
+    }
+
+    let fields = decode_fields(&buffer[4..4 + length])?;
+    Ok(Some((fields, 4 + length)))
+}
+
+/// Reads the next frame from the reader, blocking until it has been received in full.
+pub fn read<R: Read>(reader: &mut R) -> io::Result<Vec<Vec<u8>>> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let mut payload = vec![0; read_length(&length)];
+    reader.read_exact(&mut payload)?;
+    decode_fields(&payload)
+}
+
+fn decode_fields(mut payload: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut fields = Vec::new();
+    while !payload.is_empty() {
+        if payload.len() < 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated field length"));
+        }
+        let length = read_length(payload);
+        if payload.len() < 4 + length {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated field"));
+        }
+        fields.push(payload[4..4 + length].to_vec());
+        payload = &payload[4 + length..];
+    }
+    Ok(fields)
+}
+
+fn read_length(buffer: &[u8]) -> usize {
+    (buffer[0] as usize) << 24 | (buffer[1] as usize) << 16 | (buffer[2] as usize) << 8
+        | buffer[3] as usize
+}
+
+/// The length must already be known to fit within 32 bits.
+fn write_length(buffer: &mut Vec<u8>, length: usize) {
+    buffer.extend_from_slice(&[
+        (length >> 24) as u8,
+        (length >> 16) as u8,
+        (length >> 8) as u8,
+        length as u8,
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, read};
+
+    #[test]
+    fn round_trip() {
+        let binary = (0..256).map(|byte| byte as u8).collect::<Vec<u8>>();
+        let fields: Vec<&[u8]> = vec![b"1 0", b"", b"\r\n\x1F\\n\n", &binary];
+
+        let mut buffer = Vec::new();
+        encode(&fields, &mut buffer).unwrap();
+        encode(&[b"ERR 2"], &mut buffer).unwrap();
+        let first = buffer.len() - 13;
+
+        // Frames which have only partially been received are left alone.
+        assert_eq!(decode(&buffer[..first - 1], 1024).unwrap(), None);
+
+        // Unless they are longer than the maximum, which is known from their length alone.
+        assert!(decode(&buffer[..4], first - 5).is_err());
+
+        let (decoded, consumed) = decode(&buffer, 1024).unwrap().unwrap();
+        assert_eq!(consumed, first);
+        assert_eq!(decoded, fields.iter().map(|field| field.to_vec()).collect::<Vec<_>>());
+
+        let mut reader = &buffer[first..];
+        assert_eq!(read(&mut reader).unwrap(), vec![b"ERR 2".to_vec()]);
+        assert!(read(&mut reader).is_err());
+    }
+}
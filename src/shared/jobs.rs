@@ -1,18 +1,27 @@
-use super::{InsertJob, InsertOutput, Token, Tokens};
+use super::{Deadline, InsertJob, InsertOutput, Mode, Stream, Token, Tokens};
 use libc::{self, close, dup2};
+use std::collections::HashSet;
 use std::env;
+use std::fmt::{self, Display, Formatter};
 use std::fs::File;
-use std::os::unix::io::FromRawFd;
-use std::os::unix::process::CommandExt;
-use std::process::Command;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const STDOUT_FILENO: i32 = libc::STDOUT_FILENO;
 const STDERR_FILENO: i32 = libc::STDERR_FILENO;
 
+/// How long jobs are given to exit after being terminated, before they are killed.
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+
 lazy_static! {
     /// On non-Windows systems, the `SHELL` environment variable will be used to determine the
     /// preferred shell of choice for execution. Windows will simply use `cmd`.
@@ -25,87 +34,608 @@ lazy_static! {
 
 #[derive(Clone)]
 pub struct Job<INPUTS: InsertJob, OUTPUTS: InsertOutput> {
-    pub slots:   usize,
-    pub command: Tokens,
-    pub inputs:  Arc<INPUTS>,
-    pub outputs: Arc<OUTPUTS>,
-    pub kill:    Arc<AtomicBool>,
-    pub parked:  Arc<AtomicUsize>,
+    pub slots:    usize,
+    pub command:  Tokens,
+    pub inputs:   Arc<INPUTS>,
+    pub outputs:  Arc<OUTPUTS>,
+    pub parked:   Arc<AtomicUsize>,
+    pub running:  Arc<Running>,
+    pub deadline: Option<Arc<Deadline>>,
+}
+
+/// How a job that was executed came to an end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    /// The job exited with the given exit status.
+    Exited(u8),
+    /// The job was killed, as it did not exit before its deadline.
+    TimedOut,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Status::Exited(status) => write!(f, "{}", status),
+            Status::TimedOut => f.write_str("timeout"),
+        }
+    }
+}
+
+impl FromStr for Status {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Status, ()> {
+        match input {
+            "timeout" => Ok(Status::TimedOut),
+            _ => input.parse::<u8>().map(Status::Exited).map_err(|_| ()),
+        }
+    }
+}
+
+/// When, and for how long, a job ran, and the signal that terminated it, such as
+/// `1508000000.125 2.500 0`. Times are written as seconds, with millisecond precision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub start:   SystemTime,
+    pub runtime: Duration,
+    /// The signal that terminated the job, or `0` if it exited by itself.
+    pub signal:  u8,
+}
+
+impl Display for Timing {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let start = self.start.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(f, "{} {} {}", Seconds(start), Seconds(self.runtime), self.signal)
+    }
+}
+
+impl FromStr for Timing {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Timing, ()> {
+        let mut fields = input.split(' ');
+        let mut next = || fields.next().ok_or(());
+        let start = UNIX_EPOCH + parse_seconds(next()?)?;
+        let runtime = parse_seconds(next()?)?;
+        let signal = next()?.parse::<u8>().map_err(|_| ())?;
+        Ok(Timing {
+            start,
+            runtime,
+            signal,
+        })
+    }
+}
+
+/// Writes a duration as seconds, with millisecond precision.
+pub struct Seconds(pub Duration);
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}.{:03}", self.0.as_secs(), self.0.subsec_nanos() / 1_000_000)
+    }
+}
+
+fn parse_seconds(input: &str) -> Result<Duration, ()> {
+    match input.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => {
+            Ok(Duration::from_millis((seconds * 1000.0).round() as u64))
+        }
+        _ => Err(()),
+    }
+}
+
+/// Describes a job that was executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Execution {
+    pub status:  Status,
+    pub timing:  Timing,
+    /// The command line, after the inputs were substituted into it.
+    pub command: String,
+}
+
+/// Tracks the process group of each job that is running, so that they may be killed.
+pub struct Running {
+    /// The process groups, and whether every job has been killed.
+    state: Mutex<(HashSet<i32>, bool)>,
+}
+
+impl Running {
+    pub fn new() -> Running {
+        Running {
+            state: Mutex::new((HashSet::new(), false)),
+        }
+    }
+
+    /// Jobs that start after every job has been killed are terminated immediately.
+    fn insert(&self, pid: i32) {
+        let mut state = self.state.lock().unwrap();
+        if state.1 {
+            signal_group(pid, libc::SIGTERM);
+        }
+        state.0.insert(pid);
+    }
+
+    fn remove(&self, pid: i32) { self.state.lock().unwrap().0.remove(&pid); }
+
+    /// Terminates every job that is running, along with any processes that they spawned, and
+    /// then kills the jobs which have not exited within the grace period.
+    pub fn kill_all(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.1 = true;
+            for &pid in &state.0 {
+                signal_group(pid, libc::SIGTERM);
+            }
+        }
+
+        let start = Instant::now();
+        while Instant::now() - start < GRACE_PERIOD {
+            if self.state.lock().unwrap().0.is_empty() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        for &pid in &self.state.lock().unwrap().0 {
+            signal_group(pid, libc::SIGKILL);
+        }
+    }
 }
 
+/// Executes jobs from the inputs until no more inputs will be supplied, parking whenever the
+/// inputs are empty.
 pub fn slot_event<INPUTS: InsertJob, OUTPUTS: InsertOutput>(
     sid: usize,
     command: Tokens,
     inputs: Arc<INPUTS>,
     outputs: Arc<OUTPUTS>,
-    kill: Arc<AtomicBool>,
     parked: Arc<AtomicUsize>,
+    running: Arc<Running>,
+    deadline: Option<Arc<Deadline>>,
 ) {
     let mut buffer = String::new();
+    let mut arguments = Vec::new();
 
-    while kill.load(Ordering::Relaxed) != true {
+    // Inputs are quoted so that the shell will not interpret them, unless requested. When
+    // executing directly, each token is already a separate argument.
+    let substitute: fn(&mut String, &str) = match command.mode {
+        Mode::Shell => quote,
+        Mode::Exec => |buffer, input| buffer.push_str(input),
+    };
+
+    while let Some((jid, input)) = inputs.get_job() {
         buffer.clear();
-        thread::sleep(Duration::from_millis(1));
-        if let Some((jid, input)) = inputs.get_job() {
-            for token in &command.tokens {
-                match *token {
-                    Token::Placeholder => buffer.push_str(&input),
-                    Token::Slot => buffer.push_str(&sid.to_string()),
-                    Token::Job => buffer.push_str(&jid.to_string()),
-                    Token::Text(ref text) => buffer.push_str(text),
+        arguments.clear();
+        for token in &command.tokens {
+            match *token {
+                Token::Placeholder => each(&mut buffer, &input, substitute, |value| value),
+                Token::Raw => buffer.push_str(&input.join(" ")),
+                Token::Slot => buffer.push_str(&sid.to_string()),
+                Token::Job => buffer.push_str(&jid.to_string()),
+                Token::Positional(id) => if let Some(value) = input.get(id - 1) {
+                    substitute(&mut buffer, value);
+                },
+                Token::NoExtension => each(&mut buffer, &input, substitute, remove_extension),
+                Token::Basename => each(&mut buffer, &input, substitute, basename),
+                Token::Dirname => each(&mut buffer, &input, substitute, dirname),
+                Token::BasenameNoExtension => {
+                    each(&mut buffer, &input, substitute, |value| remove_extension(basename(value)))
                 }
+                Token::Host => buffer.push_str("{host}"),
+                Token::Break => arguments.push(mem::replace(&mut buffer, String::new())),
+                Token::Text(ref text) => buffer.push_str(text),
+            }
+        }
+
+        // Outputs which are streamed are read from pipes while the job runs. Otherwise, the job
+        // writes directly to anonymous files, so that it will never block on a full pipe.
+        let streams = outputs.streams(jid);
+        let fds = if streams { pair(pipe) } else { pair(spool) };
+        let (stdout_fds, stderr_fds) = match fds {
+            Ok(fds) => fds,
+            Err(why) => {
+                eprintln!("[CRITICAL] unable to create outputs: {}", why);
+                outputs.insert(jid, None);
+                continue;
+            }
+        };
+
+        let (mut cmd, line) = match command.mode {
+            // Spawn a shell with the supplied command.
+            Mode::Shell => {
+                let mut cmd = Command::new(COMMAND.0.as_str());
+                cmd.arg(COMMAND.1).arg(&buffer);
+                (cmd, buffer.clone())
+            }
+            // Or execute the first argument directly, with the remaining arguments.
+            Mode::Exec => {
+                arguments.push(mem::replace(&mut buffer, String::new()));
+                let mut cmd = Command::new(&arguments[0]);
+                cmd.args(&arguments[1..]);
+                (cmd, arguments.join(" "))
             }
+        };
+
+        let cmd = cmd
+            // Configure the pipes accordingly in the child.
+            .before_exec(move || unsafe {
+                // Place the child within its own process group, so that it may be killed along
+                // with any processes that it spawns.
+                libc::setpgid(0, 0);
+
+                // Redirect the child's std{out,err} to the write ends of our pipe.
+                dup2(stdout_fds[1], STDOUT_FILENO);
+                dup2(stderr_fds[1], STDERR_FILENO);
+
+                // Close all the fds we created here, so EOF will be sent when the program exits.
+                close(stdout_fds[0]);
+                close(stdout_fds[1]);
+                close(stderr_fds[0]);
+                close(stderr_fds[1]);
 
-            let mut stdout_fds = [0; 2];
-            let mut stderr_fds = [0; 2];
+                Ok(())
+            })
+            .spawn();
 
+        let (mut pout, mut perr) = unsafe {
+            // Close the write ends of the pipes in the parent
+            libc::close(stdout_fds[1]);
+            libc::close(stderr_fds[1]);
+            (
+                // But create files from the read ends.
+                File::from_raw_fd(stdout_fds[0]),
+                File::from_raw_fd(stderr_fds[0]),
+            )
+        };
+
+        match cmd {
+            Ok(mut child) => {
+                let started = SystemTime::now();
+                let start = Instant::now();
+                let pid = child.id() as i32;
+                running.insert(pid);
+                let limit = deadline.as_ref().and_then(|deadline| deadline.limit());
+                let (status, signal) = if streams {
+                    // The job is waited upon by another thread, so that its outputs may be read
+                    // as they are produced.
+                    let waiter = thread::spawn(move || wait(&mut child, limit));
+                    stream(jid, &*outputs, &mut pout, &mut perr);
+                    waiter.join().unwrap_or((Status::Exited(1), 0))
+                } else {
+                    let exit = wait(&mut child, limit);
+                    // The outputs will be read from the beginning of each file.
+                    let _ = pout.seek(SeekFrom::Start(0));
+                    let _ = perr.seek(SeekFrom::Start(0));
+                    exit
+                };
+                running.remove(pid);
+                let runtime = Instant::now() - start;
+                // Only the runtimes of jobs that completed are considered by the deadline.
+                if let (Some(deadline), Status::Exited(_)) = (deadline.as_ref(), status) {
+                    deadline.record(runtime);
+                }
+
+                let execution = Execution {
+                    status,
+                    timing: Timing {
+                        start: started,
+                        runtime,
+                        signal,
+                    },
+                    command: line,
+                };
+                outputs.insert(jid, Some((execution, pout, perr)));
+            }
+            Err(why) => {
+                eprintln!("[CRITICAL] {}", why);
+                outputs.insert(jid, None);
+            }
+        }
+    }
+    parked.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Expands a template for a job that has been executed on the given host, such as a tag for
+/// its outputs. Values are neither quoted nor split into arguments, and the slot that executed
+/// the job is not known, so `{%}` expands to nothing.
+pub fn expand(template: &Tokens, jid: usize, input: &[String], host: &str) -> String {
+    let push: fn(&mut String, &str) = String::push_str;
+    let mut buffer = String::new();
+    for token in &template.tokens {
+        match *token {
+            Token::Placeholder | Token::Raw => each(&mut buffer, input, push, |value| value),
+            Token::Slot => (),
+            Token::Job => buffer.push_str(&jid.to_string()),
+            Token::Positional(id) => if let Some(value) = input.get(id - 1) {
+                buffer.push_str(value);
+            },
+            Token::NoExtension => each(&mut buffer, input, push, remove_extension),
+            Token::Basename => each(&mut buffer, input, push, basename),
+            Token::Dirname => each(&mut buffer, input, push, dirname),
+            Token::BasenameNoExtension => {
+                each(&mut buffer, input, push, |value| remove_extension(basename(value)))
+            }
+            Token::Host => buffer.push_str(host),
+            Token::Break => buffer.push(' '),
+            Token::Text(ref text) => buffer.push_str(text),
+        }
+    }
+    buffer
+}
+
+/// Creates a file within the temporary directory, which is removed as soon as it has been
+/// created, so that it will be deleted once it has been closed.
+pub fn anonymous_file() -> io::Result<File> {
+    let mut template = env::temp_dir().join("concurr.XXXXXX").into_os_string().into_vec();
+    template.push(0);
+    unsafe {
+        let fd = libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::unlink(template.as_ptr() as *const libc::c_char);
+        Ok(File::from_raw_fd(fd))
+    }
+}
+
+/// The read and write ends of a pipe.
+fn pipe() -> io::Result<[i32; 2]> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fds)
+}
+
+/// An anonymous file, with a second descriptor that the job writes to, as it would to the
+/// write end of a pipe.
+fn spool() -> io::Result<[i32; 2]> {
+    let fd = anonymous_file()?.into_raw_fd();
+    match unsafe { libc::dup(fd) } {
+        dup if dup < 0 => {
+            let why = io::Error::last_os_error();
+            unsafe { close(fd) };
+            Err(why)
+        }
+        dup => Ok([fd, dup]),
+    }
+}
+
+/// Creates the descriptors for both the standard output and error of a job.
+fn pair(create: fn() -> io::Result<[i32; 2]>) -> io::Result<([i32; 2], [i32; 2])> {
+    let stdout = create()?;
+    match create() {
+        Ok(stderr) => Ok((stdout, stderr)),
+        Err(why) => {
             unsafe {
-                let _ = libc::pipe(stdout_fds.as_mut_ptr());
-                let _ = libc::pipe(stderr_fds.as_mut_ptr());
+                close(stdout[0]);
+                close(stdout[1]);
             }
+            Err(why)
+        }
+    }
+}
 
-            // Spawn a shell with the supplied command.
-            let cmd = Command::new(COMMAND.0.as_str())
-                .arg(COMMAND.1)
-                .arg(&buffer)
-                // Configure the pipes accordingly in the child.
-                .before_exec(move || unsafe {
-                    // Redirect the child's std{out,err} to the write ends of our pipe.
-                    dup2(stdout_fds[1], STDOUT_FILENO);
-                    dup2(stderr_fds[1], STDERR_FILENO);
-
-                    // Close all the fds we created here, so EOF will be sent when the program exits.
-                    close(stdout_fds[0]);
-                    close(stdout_fds[1]);
-                    close(stderr_fds[0]);
-                    close(stderr_fds[1]);
-
-                    Ok(())
-                })
-                .spawn();
-
-            let (mut pout, mut perr) = unsafe {
-                // Close the write ends of the pipes in the parent
-                libc::close(stdout_fds[1]);
-                libc::close(stderr_fds[1]);
-                (
-                    // But create files from the read ends.
-                    File::from_raw_fd(stdout_fds[0]),
-                    File::from_raw_fd(stderr_fds[0]),
-                )
+/// Waits for the child to exit, killing it if it has not exited within the limit.
+fn wait(child: &mut Child, limit: Option<Duration>) -> (Status, u8) {
+    match limit {
+        Some(limit) => wait_timeout(child, limit),
+        None => {
+            let exit = child.wait().ok();
+            (Status::Exited(exit_status(exit)), exit_signal(exit))
+        }
+    }
+}
+
+/// Passes each chunk that is read from the standard output and error of the job to the outputs,
+/// until both have been closed.
+fn stream<OUTPUTS: InsertOutput>(jid: usize, outputs: &OUTPUTS, out: &mut File, err: &mut File) {
+    let mut buffer = [0; 8 * 1024];
+    let pollfd = |file: &File| libc::pollfd {
+        fd:      file.as_raw_fd(),
+        events:  libc::POLLIN,
+        revents: 0,
+    };
+    let mut fds = [pollfd(out), pollfd(err)];
+
+    // Descriptors which are negative are ignored by `poll`, which marks the pipes that closed.
+    while fds.iter().any(|fd| fd.fd >= 0) {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+
+        for (index, fd) in fds.iter_mut().enumerate() {
+            if fd.fd < 0 || fd.revents == 0 {
+                continue;
+            }
+            let (stream, file) = match index {
+                0 => (Stream::Stdout, &mut *out),
+                _ => (Stream::Stderr, &mut *err),
             };
+            match file.read(&mut buffer) {
+                Ok(0) | Err(_) => fd.fd = -1,
+                Ok(read) => outputs.insert_chunk(jid, stream, &buffer[..read]),
+            }
+        }
+    }
+}
 
-            match cmd {
-                Ok(mut child) => {
-                    let status = child.wait().ok().map_or(1, |e| e.code().unwrap_or(1)) as u8;
-                    outputs.insert(jid, Some((status, pout, perr)));
-                }
-                Err(why) => {
-                    eprintln!("[CRITICAL] {}", why);
-                    outputs.insert(jid, None);
+/// Jobs which were killed by a signal are considered to have failed with `1`.
+fn exit_status(status: Option<ExitStatus>) -> u8 {
+    status.map_or(1, |status| status.code().unwrap_or(1)) as u8
+}
+
+/// The signal that terminated the job, or `0` if it exited by itself.
+fn exit_signal(status: Option<ExitStatus>) -> u8 {
+    status.and_then(|status| status.signal()).unwrap_or(0) as u8
+}
+
+/// Waits for the child to exit, terminating its process group if it has not exited within the
+/// limit. The process group is killed if it has not exited within the grace period thereafter.
+fn wait_timeout(child: &mut Child, limit: Duration) -> (Status, u8) {
+    let start = Instant::now();
+    // Poll frequently at first, so that short jobs are not delayed.
+    let mut interval = Duration::from_millis(1);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                return (Status::Exited(exit_status(Some(status))), exit_signal(Some(status)))
+            }
+            Ok(None) => {
+                let elapsed = Instant::now() - start;
+                if elapsed >= limit {
+                    break;
                 }
+                thread::sleep(interval.min(limit - elapsed));
+                interval = (interval * 2).min(Duration::from_millis(50));
             }
+            Err(_) => return (Status::Exited(1), 0),
         }
     }
-    parked.fetch_add(1, Ordering::Relaxed);
+
+    let pid = child.id() as i32;
+    signal_group(pid, libc::SIGTERM);
+    let start = Instant::now();
+    while Instant::now() - start < GRACE_PERIOD {
+        if let Ok(Some(status)) = child.try_wait() {
+            return (Status::TimedOut, exit_signal(Some(status)));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    signal_group(pid, libc::SIGKILL);
+    (Status::TimedOut, exit_signal(child.wait().ok()))
+}
+
+/// Substitutes the value of each input source, separated by spaces, after applying the path
+/// operation to each value, as in `{/}` becoming `b.txt d.txt` for `a/b.txt` and `c/d.txt`.
+fn each<F: Fn(&str) -> &str>(
+    buffer: &mut String,
+    input: &[String],
+    substitute: fn(&mut String, &str),
+    path: F,
+) {
+    for (id, value) in input.iter().enumerate() {
+        if id != 0 {
+            buffer.push(' ');
+        }
+        substitute(buffer, path(value));
+    }
+}
+
+/// Sends the signal to every process within the process group of the job. The group is given
+/// to `kill` as a negative ID, as older releases of libc lack `killpg`.
+fn signal_group(pid: i32, signal: libc::c_int) { unsafe { libc::kill(-pid, signal) }; }
+
+/// Strips everything up to and including the last `/`: `a/b/c.txt` becomes `c.txt`.
+fn basename(input: &str) -> &str { input.rfind('/').map_or(input, |pos| &input[pos + 1..]) }
+
+/// Strips the last `/` and everything after it: `a/b/c.txt` becomes `a/b`. Inputs which lack a
+/// directory are relative to the current directory, `.`.
+fn dirname(input: &str) -> &str {
+    match input.rfind('/') {
+        Some(0) => "/",
+        Some(pos) => &input[..pos],
+        None => ".",
+    }
+}
+
+/// Strips the extension from the file name of the input: `a/b.c/d.tar.gz` becomes
+/// `a/b.c/d.tar`. A leading `.`, as in hidden files, is not considered an extension.
+fn remove_extension(input: &str) -> &str {
+    let name_start = input.rfind('/').map_or(0, |pos| pos + 1);
+    match input[name_start..].rfind('.') {
+        Some(pos) if pos != 0 => &input[..name_start + pos],
+        _ => input,
+    }
+}
+
+/// Characters which the shell will never interpret, and therefore need not be quoted.
+fn is_safe(character: char) -> bool {
+    character.is_ascii_alphanumeric() || "_-+.,:/@".contains(character)
+}
+
+/// Appends the input to the buffer as a single shell word, within single quotes if the
+/// input contains any characters that the shell could otherwise interpret. Single quotes
+/// within the input are written as `'\''`.
+fn quote(buffer: &mut String, input: &str) {
+    if !input.is_empty() && input.chars().all(is_safe) {
+        buffer.push_str(input);
+        return;
+    }
+
+    buffer.push('\'');
+    for character in input.chars() {
+        if character == '\'' {
+            buffer.push_str("'\\''");
+        } else {
+            buffer.push(character);
+        }
+    }
+    buffer.push('\'');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{basename, dirname, expand, quote, remove_extension, Timing};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tokenizer::Tokens;
+
+    #[test]
+    fn timing() {
+        let timing = Timing {
+            start:   UNIX_EPOCH + Duration::from_millis(1_508_000_000_125),
+            runtime: Duration::from_millis(2500),
+            signal:  15,
+        };
+        assert_eq!(timing.to_string(), "1508000000.125 2.500 15");
+        assert_eq!(timing.to_string().parse::<Timing>(), Ok(timing));
+        assert!("1508000000.125 2.500".parse::<Timing>().is_err());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(basename("a/b/c.txt"), "c.txt");
+        assert_eq!(basename("c.txt"), "c.txt");
+        assert_eq!(dirname("a/b/c.txt"), "a/b");
+        assert_eq!(dirname("/c.txt"), "/");
+        assert_eq!(dirname("c.txt"), ".");
+        assert_eq!(remove_extension("a/b.c/d.tar.gz"), "a/b.c/d.tar");
+        assert_eq!(remove_extension("a.b/c"), "a.b/c");
+        assert_eq!(remove_extension("a/.hidden"), "a/.hidden");
+        assert_eq!(remove_extension(basename("a/b/c.txt")), "c");
+    }
+
+    #[test]
+    fn templates() {
+        let input = vec!["a/b.txt".to_owned(), "it's".to_owned()];
+        let template = Tokens::template("{#}:{host}:{2}:{1}{%}");
+        assert_eq!(expand(&template, 4, &input, ":"), "4:::it's:a/b.txt");
+        assert_eq!(expand(&Tokens::template("{}"), 4, &input, ":"), "a/b.txt it's");
+        assert_eq!(expand(&Tokens::template("{/.}"), 4, &input[..1], ":"), "b");
+
+        // Paths are taken from the value of each input source.
+        let input = vec!["a/b.txt".to_owned(), "c/d.tar.gz".to_owned()];
+        assert_eq!(expand(&Tokens::template("{/.}"), 4, &input, ":"), "b d.tar");
+        assert_eq!(expand(&Tokens::template("{//}"), 4, &input, ":"), "a c");
+        assert_eq!(expand(&Tokens::template("{.}"), 4, &input, ":"), "a/b c/d.tar");
+    }
+
+    #[test]
+    fn quoting() {
+        let quoted = |input| {
+            let mut buffer = String::new();
+            quote(&mut buffer, input);
+            buffer
+        };
+
+        assert_eq!(quoted("a/b-c.txt"), "a/b-c.txt");
+        assert_eq!(quoted(""), "''");
+        assert_eq!(quoted("a b"), "'a b'");
+        assert_eq!(quoted("$(rm -rf ~); `x`"), "'$(rm -rf ~); `x`'");
+        assert_eq!(quoted("it's"), "'it'\\''s'");
+    }
 }
@@ -1,20 +1,32 @@
 extern crate app_dirs;
-extern crate coco;
 #[macro_use]
 extern crate lazy_static;
 extern crate libc;
 
 mod tokenizer;
 mod jobs;
+mod limit;
+mod queue;
+mod timeout;
+pub mod frame;
+pub mod protocol;
 
-pub use self::jobs::{slot_event, Job};
-pub use self::tokenizer::{Token, Tokens};
+pub use self::jobs::{anonymous_file, expand, slot_event, Execution, Job, Running, Seconds,
+                     Status, Timing};
+pub use self::limit::Limit;
+pub use self::queue::{Mailbox, Queue};
+pub use self::timeout::{Deadline, Timeout};
+pub use self::tokenizer::{Mode, Token, Tokens};
 use app_dirs::AppInfo;
 use std::fs::File;
 
+/// Each job carries one value per input source, such as each of the `:` / `::` argument groups
+/// that were permutated to generate the job.
 pub trait InsertJob {
-    fn get_job(&self) -> Option<(usize, String)>;
-    fn insert_job(&self, usize, String);
+    /// Parks the calling slot until a job is available. Once no more jobs will be supplied,
+    /// `None` is returned, which signals the slot to exit.
+    fn get_job(&self) -> Option<(usize, Vec<String>)>;
+    fn insert_job(&self, usize, Vec<String>);
 }
 
 pub const APP_INFO: AppInfo = AppInfo {
@@ -22,6 +34,21 @@ pub const APP_INFO: AppInfo = AppInfo {
     author: "Michael Aaron Murphy",
 };
 
+/// The output streams of a job.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Implementors should wake any thread that is waiting on the result of the inserted job.
 pub trait InsertOutput {
-    fn insert(&self, id: usize, result: Option<(u8, File, File)>);
+    fn insert(&self, id: usize, result: Option<(Execution, File, File)>);
+
+    /// Whether the outputs of the job are passed to `insert_chunk` as they are produced. The
+    /// files that are inserted once the job has exited will then have been read to the end.
+    fn streams(&self, _id: usize) -> bool { false }
+
+    /// Receives a chunk of the output of a job that is running, if outputs are streamed.
+    fn insert_chunk(&self, _id: usize, _stream: Stream, _chunk: &[u8]) {}
 }
@@ -0,0 +1,114 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// A number of bytes, such as `512`, which may be suffixed with `K`, `M`, or `G` for powers of
+/// 1024, such as `64K`.
+///
+/// When it limits the outputs of a job, the bytes of each output stream which exceed the limit
+/// are discarded, and replaced with a line that marks where the stream was truncated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit(pub u64);
+
+impl FromStr for Limit {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Limit, ()> {
+        let (number, unit) = match input.char_indices().last() {
+            Some((pos, 'K')) | Some((pos, 'k')) => (&input[..pos], 1 << 10),
+            Some((pos, 'M')) | Some((pos, 'm')) => (&input[..pos], 1 << 20),
+            Some((pos, 'G')) | Some((pos, 'g')) => (&input[..pos], 1 << 30),
+            _ => (input, 1),
+        };
+        let number = number.parse::<u64>().map_err(|_| ())?;
+        number.checked_mul(unit).map(Limit).ok_or(())
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "{}", self.0) }
+}
+
+impl Limit {
+    /// The line that is written in place of the bytes which exceed the limit.
+    pub fn marker(&self) -> String {
+        format!("\n[concurr: output truncated after {} bytes]\n", self.0)
+    }
+
+    /// Takes as much of the next chunk of a stream as may be kept, where `seen` is the number of
+    /// bytes of the stream that preceded it, which is then updated. The returned flag is set
+    /// once, for the chunk which exceeded the limit, after which the marker should be written.
+    fn take<'a>(&self, seen: &mut u64, chunk: &'a [u8]) -> (&'a [u8], bool) {
+        let start = *seen;
+        *seen += chunk.len() as u64;
+        if start > self.0 {
+            return (&[], false);
+        }
+        let kept = (self.0 - start).min(chunk.len() as u64) as usize;
+        (&chunk[..kept], *seen > self.0)
+    }
+
+    /// Like `take`, but the marker is included after the bytes that were kept.
+    pub fn keep(&self, seen: &mut u64, chunk: &[u8]) -> Vec<u8> {
+        let (kept, truncated) = self.take(seen, chunk);
+        let mut kept = kept.to_vec();
+        if truncated {
+            kept.extend_from_slice(self.marker().as_bytes());
+        }
+        kept
+    }
+
+    /// Appends as much of the chunk to the buffer as may be kept, followed by the marker once
+    /// the limit has been exceeded. Buffers which are longer than the limit have been marked.
+    pub fn append(&self, buffer: &mut Vec<u8>, chunk: &[u8]) {
+        let mut seen = buffer.len() as u64;
+        if seen > self.0 {
+            return;
+        }
+        let (kept, truncated) = self.take(&mut seen, chunk);
+        buffer.extend_from_slice(kept);
+        if truncated {
+            buffer.extend_from_slice(self.marker().as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Limit;
+
+    #[test]
+    fn sizes() {
+        assert_eq!("512".parse::<Limit>(), Ok(Limit(512)));
+        assert_eq!("64K".parse::<Limit>(), Ok(Limit(64 * 1024)));
+        assert_eq!("2m".parse::<Limit>(), Ok(Limit(2 * 1024 * 1024)));
+        assert_eq!("1G".parse::<Limit>(), Ok(Limit(1024 * 1024 * 1024)));
+        assert!("".parse::<Limit>().is_err());
+        assert!("K".parse::<Limit>().is_err());
+        assert!("-1".parse::<Limit>().is_err());
+        assert!("1T".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn truncation() {
+        let limit = Limit(4);
+        let mut seen = 0;
+        assert_eq!(limit.take(&mut seen, b"ab"), (&b"ab"[..], false));
+        assert_eq!(limit.take(&mut seen, b"cd"), (&b"cd"[..], false));
+        assert_eq!(limit.take(&mut seen, b"e"), (&b""[..], true));
+        assert_eq!(limit.take(&mut seen, b"f"), (&b""[..], false));
+
+        let mut seen = 3;
+        let mut expected = b"d".to_vec();
+        expected.extend_from_slice(limit.marker().as_bytes());
+        assert_eq!(limit.keep(&mut seen, b"de"), expected);
+        assert_eq!(limit.keep(&mut seen, b"f"), b"");
+
+        let mut buffer = Vec::new();
+        limit.append(&mut buffer, b"abc");
+        limit.append(&mut buffer, b"def");
+        limit.append(&mut buffer, b"ghi");
+        let mut expected = b"abcd".to_vec();
+        expected.extend_from_slice(limit.marker().as_bytes());
+        assert_eq!(buffer, expected);
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt::{self, Display, Formatter};
+
+// # Versions
+// - 1: Instructions and responses are sent as lines.
+// - 2: Adds the `hello` exchange, and the `set framed` upgrade to length-prefixed frames.
+
+/// The version of the protocol that is spoken by this build.
+pub const VERSION: u32 = 2;
+
+/// Inputs and outputs may be sent as length-prefixed frames, via `set framed`.
+pub const FRAMING: u8 = 1;
+/// Commands may be executed directly, without a shell, via `com +exec`.
+pub const EXEC: u8 = 2;
+/// Outputs may be sent while the job is still running, via `set streaming`.
+pub const STREAMING: u8 = 4;
+/// Running jobs may be killed when a command is deleted, via `del +kill`.
+pub const KILL: u8 = 16;
+/// Jobs may be killed once they exceed a timeout, via `com +timeout=N`.
+pub const TIMEOUT: u8 = 32;
+/// The outputs of each job may be truncated once they exceed a size, via `com +max-output=N`.
+pub const LIMIT: u8 = 64;
+
+/// The features that are supported by this build.
+pub const FEATURES: u8 = FRAMING | EXEC | STREAMING | KILL | TIMEOUT | LIMIT;
+
+const NAMES: [(u8, &str); 6] = [
+    (FRAMING, "framing"),
+    (EXEC, "exec"),
+    (STREAMING, "streaming"),
+    (KILL, "kill"),
+    (TIMEOUT, "timeout"),
+    (LIMIT, "limit"),
+];
+
+/// Announces the protocol version and features that one side of a connection supports, such
+/// as `hello 2 framing exec`. Features which are not recognized are ignored, so that newer
+/// peers may announce features that older peers are unaware of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hello {
+    pub version:  u32,
+    pub features: u8,
+}
+
+impl Hello {
+    /// The `hello` that will be announced by this build.
+    pub fn new() -> Hello {
+        Hello {
+            version:  VERSION,
+            features: FEATURES,
+        }
+    }
+
+    /// Peers which do not support the `hello` exchange only speak the first version of the
+    /// protocol, which lacks every feature except for executing commands in a shell.
+    pub fn legacy() -> Hello {
+        Hello {
+            version:  1,
+            features: 0,
+        }
+    }
+
+    /// Parses a `hello` line, returning `None` if the line is not a `hello`.
+    pub fn parse(line: &str) -> Option<Hello> {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("hello") {
+            return None;
+        }
+
+        let version = words.next()?.parse::<u32>().ok()?;
+        let features = words
+            .filter_map(|word| NAMES.iter().find(|&&(_, name)| name == word))
+            .fold(0, |features, &(feature, _)| features | feature);
+
+        Some(Hello { version, features })
+    }
+
+    /// Whether the given feature is supported.
+    pub fn supports(&self, feature: u8) -> bool { self.features & feature != 0 }
+}
+
+impl Display for Hello {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "hello {}", self.version)?;
+        for &(feature, name) in &NAMES {
+            if self.supports(feature) {
+                write!(f, " {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hello, EXEC, FRAMING};
+
+    #[test]
+    fn hello() {
+        let hello = Hello::new();
+        assert_eq!(hello.to_string(), "hello 2 framing exec streaming kill timeout limit");
+        assert_eq!(Hello::parse(&hello.to_string()), Some(hello));
+
+        let hello = Hello::parse("hello 3 exec teleport compression").unwrap();
+        assert_eq!(hello.version, 3);
+        assert_eq!(hello.features, EXEC);
+        assert!(!hello.supports(FRAMING));
+
+        assert_eq!(Hello::parse("hello"), None);
+        assert_eq!(Hello::parse("ERR 1 invalid instruction"), None);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+
+/// A queue that is shared between threads, whose consumers will park until an element has
+/// been pushed, or until the queue has been closed.
+///
+/// # Ordering
+///
+/// Elements are taken in first-in, first-out order. Each element may optionally be given a
+/// priority, which is `0` by default. Elements with a higher priority are always taken before
+/// elements of a lower priority, and elements of the same priority are taken in FIFO order.
+///
+/// # Bounds
+///
+/// Producers which push with a bound will park while the queue holds that many elements, so
+/// that they may not outpace the consumers.
+pub struct Queue<T> {
+    state:   Mutex<QueueState<T>>,
+    condvar: Condvar,
+    /// Wakes the producers that are parked until there is room in the queue.
+    space:   Condvar,
+}
+
+struct QueueState<T> {
+    /// Each priority has its own FIFO queue. Empty queues are removed.
+    elements: BTreeMap<u8, VecDeque<T>>,
+    len:      usize,
+    closed:   bool,
+    /// The number of producers that are parked until there is room in the queue.
+    parked:   usize,
+}
+
+impl<T> Queue<T> {
+    pub fn new() -> Queue<T> {
+        Queue {
+            state:   Mutex::new(QueueState {
+                elements: BTreeMap::new(),
+                len:      0,
+                closed:   false,
+                parked:   0,
+            }),
+            condvar: Condvar::new(),
+            space:   Condvar::new(),
+        }
+    }
+
+    /// Appends an element to the back of the queue, waking a parked consumer.
+    pub fn push_back(&self, element: T) { self.push_priority(element, 0); }
+
+    /// Appends an element to the back of the queue for the given priority.
+    pub fn push_priority(&self, element: T, priority: u8) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.elements.entry(priority).or_insert_with(VecDeque::new).push_back(element);
+            state.len += 1;
+        }
+        self.condvar.notify_one();
+    }
+
+    /// Appends an element to the back of the queue, parking while the queue holds `bound`
+    /// elements. Once the queue has been closed, the element is discarded.
+    pub fn push_bounded(&self, element: T, bound: usize) {
+        {
+            let mut state = self.state.lock().unwrap();
+            while state.len >= bound && !state.closed {
+                state.parked += 1;
+                state = self.space.wait(state).unwrap();
+                state.parked -= 1;
+            }
+            if state.closed {
+                return;
+            }
+            state.elements.entry(0).or_insert_with(VecDeque::new).push_back(element);
+            state.len += 1;
+        }
+        self.condvar.notify_one();
+    }
+
+    /// Places an element ahead of every other element that has the default priority.
+    pub fn push_front(&self, element: T) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.elements.entry(0).or_insert_with(VecDeque::new).push_front(element);
+            state.len += 1;
+        }
+        self.condvar.notify_one();
+    }
+
+    /// Takes the next element, according to the ordering of the queue, parking until one is
+    /// available. Once the queue has been closed, `None` will be returned.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if let Some(element) = state.pop_highest() {
+                self.space.notify_one();
+                return Some(element);
+            }
+            state = self.condvar.wait(state).unwrap();
+        }
+    }
+
+    /// Takes the next element if one is available, without parking.
+    pub fn try_pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        let element = if state.closed { None } else { state.pop_highest() };
+        if element.is_some() {
+            self.space.notify_one();
+        }
+        element
+    }
+
+    /// Wakes every parked consumer and producer, and signals that no more elements will be
+    /// taken.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.condvar.notify_all();
+        self.space.notify_all();
+    }
+
+    /// The number of elements that are currently waiting in the queue.
+    pub fn len(&self) -> usize { self.state.lock().unwrap().len }
+
+    /// The number of producers that are parked until there is room in the queue.
+    pub fn parked(&self) -> usize { self.state.lock().unwrap().parked }
+}
+
+impl<T> QueueState<T> {
+    fn pop_highest(&mut self) -> Option<T> {
+        let priority = match self.elements.keys().next_back() {
+            Some(&priority) => priority,
+            None => return None,
+        };
+
+        let (element, empty) = {
+            let queue = self.elements.get_mut(&priority).unwrap();
+            (queue.pop_front(), queue.is_empty())
+        };
+
+        if empty {
+            self.elements.remove(&priority);
+        }
+        self.len -= 1;
+        element
+    }
+}
+
+/// Stores results by their job ID, so that consumers may wait for a specific result.
+pub struct Mailbox<T> {
+    results: Mutex<HashMap<usize, T>>,
+    condvar: Condvar,
+}
+
+impl<T> Mailbox<T> {
+    pub fn new() -> Mailbox<T> {
+        Mailbox {
+            results: Mutex::new(HashMap::new()),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Stores the result of a job, and wakes the consumers that are waiting on a result.
+    pub fn insert(&self, id: usize, result: T) {
+        self.results.lock().unwrap().insert(id, result);
+        self.condvar.notify_all();
+    }
+
+    /// Parks until the result of the given job has been inserted, and then takes it.
+    pub fn remove(&self, id: usize) -> T {
+        let mut results = self.results.lock().unwrap();
+        loop {
+            if let Some(result) = results.remove(&id) {
+                return result;
+            }
+            results = self.condvar.wait(results).unwrap();
+        }
+    }
+
+    /// Parks until the result of the given job has been inserted, and then takes it, unless
+    /// `done` returns `true` while no result is available. Consumers are woken to check `done`
+    /// whenever a result is inserted, or `wake` is called.
+    pub fn remove_unless<F: Fn() -> bool>(&self, id: usize, done: F) -> Option<T> {
+        let mut results = self.results.lock().unwrap();
+        loop {
+            if let Some(result) = results.remove(&id) {
+                return Some(result);
+            }
+            if done() {
+                return None;
+            }
+            results = self.condvar.wait(results).unwrap();
+        }
+    }
+
+    /// Parks until any result has been inserted, and then takes the one with the lowest job ID,
+    /// unless `done` returns `true` while no result is available.
+    pub fn remove_any<F: Fn() -> bool>(&self, done: F) -> Option<(usize, T)> {
+        let mut results = self.results.lock().unwrap();
+        loop {
+            if let Some(id) = results.keys().min().cloned() {
+                return results.remove(&id).map(|result| (id, result));
+            }
+            if done() {
+                return None;
+            }
+            results = self.condvar.wait(results).unwrap();
+        }
+    }
+
+    /// Wakes the consumers that are waiting on a result, so that they may check whether they
+    /// are done.
+    pub fn wake(&self) {
+        let _results = self.results.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    /// Takes every result that has been inserted, ordered by their job IDs.
+    pub fn drain(&self) -> Vec<(usize, T)> {
+        let mut results = self.results.lock().unwrap().drain().collect::<Vec<_>>();
+        results.sort_by_key(|&(id, _)| id);
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mailbox, Queue};
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn ordering() {
+        let queue = Queue::new();
+        queue.push_back(1);
+        queue.push_back(2);
+        queue.push_priority(3, 5);
+        queue.push_front(4);
+        queue.push_priority(5, 5);
+        queue.push_priority(6, 1);
+
+        assert_eq!(queue.len(), 6);
+        let order = (0..6).map(|_| queue.pop().unwrap()).collect::<Vec<_>>();
+        assert_eq!(order, vec![3, 5, 6, 4, 1, 2]);
+        assert_eq!(queue.len(), 0);
+
+        queue.push_back(7);
+        queue.close();
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn bounded() {
+        let queue = Arc::new(Queue::new());
+        queue.push_bounded(1, 2);
+        queue.push_bounded(2, 2);
+
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || (3..6).for_each(|element| queue.push_bounded(element, 2)))
+        };
+        while queue.parked() == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(queue.len(), 2);
+
+        let order = (0..5).map(|_| queue.pop().unwrap()).collect::<Vec<_>>();
+        assert_eq!(order, vec![1, 2, 3, 4, 5]);
+        producer.join().unwrap();
+
+        // Once closed, producers no longer park, and their elements are discarded.
+        queue.push_bounded(6, 1);
+        queue.close();
+        queue.push_bounded(7, 1);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn mailbox() {
+        let mailbox = Mailbox::new();
+        mailbox.insert(3, 'c');
+        mailbox.insert(1, 'a');
+        mailbox.insert(2, 'b');
+
+        let done = Cell::new(false);
+        assert_eq!(mailbox.remove_unless(2, || done.get()), Some('b'));
+        assert_eq!(mailbox.remove_any(|| done.get()), Some((1, 'a')));
+        assert_eq!(mailbox.remove_any(|| done.get()), Some((3, 'c')));
+
+        done.set(true);
+        assert_eq!(mailbox.remove_unless(4, || done.get()), None);
+        assert_eq!(mailbox.remove_any(|| done.get()), None);
+    }
+}
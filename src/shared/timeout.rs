@@ -0,0 +1,142 @@
+use super::Seconds;
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Percentages are not applied until at least this many jobs have completed.
+const MIN_JOBS: usize = 3;
+
+/// Percentages are applied to the median runtime of, at most, this many of the jobs that most
+/// recently completed.
+const WINDOW: usize = 1024;
+
+/// How long a job may run before it is killed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timeout {
+    /// A number of seconds, such as `30` or `2.5`.
+    Duration(Duration),
+    /// A percentage of the median runtime of the jobs that have completed, such as `200%`.
+    Percent(u32),
+}
+
+impl FromStr for Timeout {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Timeout, ()> {
+        if input.ends_with('%') {
+            match input[..input.len() - 1].parse::<u32>() {
+                Ok(percent) if percent > 0 => Ok(Timeout::Percent(percent)),
+                _ => Err(()),
+            }
+        } else {
+            match input.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                    let millis = (seconds * 1000.0) as u64;
+                    Ok(Timeout::Duration(Duration::from_millis(millis.max(1))))
+                }
+                _ => Err(()),
+            }
+        }
+    }
+}
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Timeout::Duration(duration) => write!(f, "{}", Seconds(duration)),
+            Timeout::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Determines how long each job of a command may run, recording the runtimes of the jobs that
+/// completed so that percentages may be applied to their median.
+pub struct Deadline {
+    timeout:  Timeout,
+    runtimes: Mutex<Runtimes>,
+}
+
+/// The runtimes of the jobs that most recently completed.
+#[derive(Default)]
+struct Runtimes {
+    /// Kept in the order that they were recorded, so that the oldest may be discarded.
+    recent: VecDeque<Duration>,
+    /// Kept in sorted order, so that the median is always at the center.
+    sorted: Vec<Duration>,
+}
+
+impl Deadline {
+    pub fn new(timeout: Timeout) -> Deadline {
+        Deadline {
+            timeout,
+            runtimes: Mutex::new(Runtimes::default()),
+        }
+    }
+
+    /// The time that the next job may run for, if it is limited.
+    pub fn limit(&self) -> Option<Duration> {
+        match self.timeout {
+            Timeout::Duration(duration) => Some(duration),
+            Timeout::Percent(percent) => {
+                let sorted = &self.runtimes.lock().unwrap().sorted;
+                if sorted.len() < MIN_JOBS {
+                    return None;
+                }
+                let median = sorted[sorted.len() / 2];
+                Some(median * percent / 100)
+            }
+        }
+    }
+
+    /// Records the runtime of a job that completed within its limit, discarding the oldest
+    /// runtime once the window is full.
+    pub fn record(&self, runtime: Duration) {
+        if let Timeout::Percent(_) = self.timeout {
+            let runtimes = &mut *self.runtimes.lock().unwrap();
+            if runtimes.recent.len() == WINDOW {
+                let oldest = runtimes.recent.pop_front().unwrap();
+                if let Ok(pos) = runtimes.sorted.binary_search(&oldest) {
+                    runtimes.sorted.remove(pos);
+                }
+            }
+            runtimes.recent.push_back(runtime);
+            let pos = match runtimes.sorted.binary_search(&runtime) {
+                Ok(pos) | Err(pos) => pos,
+            };
+            runtimes.sorted.insert(pos, runtime);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deadline, Timeout, WINDOW};
+    use std::time::Duration;
+
+    #[test]
+    fn timeouts() {
+        let timeout = "2.5".parse::<Timeout>().unwrap();
+        assert_eq!(timeout, Timeout::Duration(Duration::from_millis(2500)));
+        assert_eq!(timeout.to_string().parse::<Timeout>(), Ok(timeout));
+        assert_eq!("200%".parse::<Timeout>(), Ok(Timeout::Percent(200)));
+        assert!("0".parse::<Timeout>().is_err());
+        assert!("-1".parse::<Timeout>().is_err());
+        assert!("0%".parse::<Timeout>().is_err());
+
+        let deadline = Deadline::new(Timeout::Percent(200));
+        deadline.record(Duration::from_secs(3));
+        deadline.record(Duration::from_secs(1));
+        assert_eq!(deadline.limit(), None);
+        deadline.record(Duration::from_secs(2));
+        assert_eq!(deadline.limit(), Some(Duration::from_secs(4)));
+
+        // Only the most recent runtimes are considered.
+        for _ in 0..WINDOW {
+            deadline.record(Duration::from_secs(10));
+        }
+        assert_eq!(deadline.limit(), Some(Duration::from_secs(20)));
+        assert_eq!(deadline.runtimes.lock().unwrap().sorted.len(), WINDOW);
+    }
+}
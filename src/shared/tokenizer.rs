@@ -1,69 +1,80 @@
 use std::fmt::{self, Display, Formatter};
+use std::mem;
 
 // # Supported Tokens
 // - {}: Placeholder
+// - {=raw=}: Placeholder Without Shell Quoting
 // - {%}: Slot Number
 // - {#}: Job Number
+// - {N}: Nth Input Source or Column
+// - {.}: Placeholder Without Extension
+// - {/}: Basename of Placeholder
+// - {//}: Dirname of Placeholder
+// - {/.}: Basename of Placeholder Without Extension
+// - {host}: Node That Executed The Job, Within Tags
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Placeholder,
+    /// The input, without quoting it for the shell: `{=raw=}`.
+    Raw,
     Slot,
     Job,
+    /// The value of the Nth input source, or the Nth column of the input, counting from 1.
+    Positional(usize),
+    /// The input with the extension removed: `{.}`.
+    NoExtension,
+    /// The file name of the input: `{/}`.
+    Basename,
+    /// The parent directory of the input: `{//}`.
+    Dirname,
+    /// The file name of the input with the extension removed: `{/.}`.
+    BasenameNoExtension,
+    /// The node that executed the job: `{host}`. As it is only known once the job has been
+    /// executed, it is only expanded within templates, and is left as written in commands.
+    Host,
+    /// Separates each argument of the command when it is executed directly.
+    Break,
     Text(String),
 }
 
+/// Determines how the expanded command will be executed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// The command is expanded into a single string that is executed by `$SHELL -c`.
+    Shell,
+    /// The command is split into arguments, and the first argument is executed directly.
+    Exec,
+}
+
 const PLACE: u8 = 1;
 const OPEN: u8 = 2;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Tokens {
     pub tokens: Vec<Token>,
+    pub mode:   Mode,
 }
 
 impl Tokens {
-    pub fn new(input: &str) -> Tokens {
-        let mut tokens = Vec::new();
-        let mut start = 0;
-        let mut flags = 0;
-        let mut chars = input.char_indices();
-
-        while let Some((id, character)) = chars.next() {
-            match character {
-                '\\' => {
-                    let _ = chars.next();
-                }
-                '{' if flags & OPEN == 0 => {
-                    flags |= OPEN;
-                    if start != id {
-                        tokens.push(Token::Text(String::from(&input[start..id])));
-                        start = id;
-                    }
-                }
-                '}' if flags & OPEN != 0 => {
-                    flags ^= OPEN;
-                    match &input[start..id + 1] {
-                        "{}" => {
-                            tokens.push(Token::Placeholder);
-                            flags |= PLACE;
-                        }
-                        "{%}" => tokens.push(Token::Slot),
-                        "{#}" => tokens.push(Token::Job),
-                        _ => continue,
-                    }
-                    start = id + 1;
-                }
-                _ => (),
-            }
-        }
+    pub fn new(input: &str) -> Tokens { Tokens::with_mode(input, Mode::Shell) }
+
+    /// In the `Exec` mode, the command is also split into arguments, as the shell would, so
+    /// that each token is substituted as exactly one argument.
+    pub fn with_mode(input: &str, mode: Mode) -> Tokens {
+        let (mut tokens, placed) = tokenize(input);
 
-        // Take care of any stragglers left behind.
-        if start != input.len() {
-            tokens.push(Token::Text(String::from(&input[start..])));
+        if mode == Mode::Exec {
+            tokens = split_arguments(tokens);
         }
 
         // If a placeholder token was not supplied, append one at the end of the command.
-        if flags & PLACE == 0 {
+        if !placed && mode == Mode::Exec {
+            if !tokens.is_empty() {
+                tokens.push(Token::Break);
+            }
+            tokens.push(Token::Placeholder);
+        } else if !placed {
             let mut append_text = false;
             match tokens.last_mut() {
                 Some(&mut Token::Text(ref mut string)) => string.push(' '),
@@ -76,7 +87,161 @@ impl Tokens {
             tokens.push(Token::Placeholder)
         }
 
-        Tokens { tokens }
+        Tokens { tokens, mode }
+    }
+
+    /// Parses a template, such as a tag, which is not a command. A placeholder is therefore
+    /// not appended when the template lacks one.
+    pub fn template(input: &str) -> Tokens {
+        Tokens {
+            tokens: tokenize(input).0,
+            mode:   Mode::Shell,
+        }
+    }
+}
+
+/// Splits the input into tokens, and reports whether any token refers to the input.
+fn tokenize(input: &str) -> (Vec<Token>, bool) {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut open = 0;
+    let mut flags = 0;
+    let mut chars = input.char_indices();
+
+    while let Some((id, character)) = chars.next() {
+        match character {
+            '\\' => {
+                let _ = chars.next();
+            }
+            '{' => {
+                flags |= OPEN;
+                open = id;
+            }
+            '}' if flags & OPEN != 0 => {
+                flags ^= OPEN;
+                // Unrecognized brace groups are left behind as text.
+                let token = match parse_token(&input[open + 1..id]) {
+                    Some(token) => token,
+                    None => continue,
+                };
+
+                if start != open {
+                    tokens.push(Token::Text(String::from(&input[start..open])));
+                }
+
+                match token {
+                    Token::Slot | Token::Job | Token::Host => (),
+                    _ => flags |= PLACE,
+                }
+
+                tokens.push(token);
+                start = id + 1;
+            }
+            _ => (),
+        }
+    }
+
+    // Take care of any stragglers left behind.
+    if start != input.len() {
+        tokens.push(Token::Text(String::from(&input[start..])));
+    }
+
+    (tokens, flags & PLACE != 0)
+}
+
+/// Splits the text surrounding each token into arguments on whitespace that is neither quoted
+/// nor escaped, removing the quotes and escapes as the shell would.
+fn split_arguments(tokens: Vec<Token>) -> Vec<Token> {
+    let mut output = Vec::new();
+    let mut argument = String::new();
+    // Whether an argument has been started, and how many tokens have been written for it.
+    let mut started = false;
+    let mut written = 0;
+    let mut quote = None;
+
+    for token in tokens {
+        let text = match token {
+            Token::Text(text) => text,
+            token => {
+                begin_argument(&mut output, &mut started);
+                if !argument.is_empty() {
+                    output.push(Token::Text(mem::replace(&mut argument, String::new())));
+                    written += 1;
+                }
+                output.push(token);
+                written += 1;
+                continue;
+            }
+        };
+
+        let mut chars = text.chars();
+        while let Some(character) = chars.next() {
+            match (quote, character) {
+                (None, character) if character.is_whitespace() => {
+                    // Quoted arguments may be empty, which still count as an argument.
+                    if started && (!argument.is_empty() || written == 0) {
+                        output.push(Token::Text(mem::replace(&mut argument, String::new())));
+                    }
+                    started = false;
+                    written = 0;
+                }
+                (None, '\\') => {
+                    begin_argument(&mut output, &mut started);
+                    argument.extend(chars.next());
+                }
+                (None, '\'') | (None, '"') => {
+                    begin_argument(&mut output, &mut started);
+                    quote = Some(character);
+                }
+                (Some('"'), '\\') => match chars.next() {
+                    Some(next) if next == '"' || next == '\\' => argument.push(next),
+                    next => {
+                        argument.push('\\');
+                        argument.extend(next);
+                    }
+                },
+                (Some(open), character) if open == character => quote = None,
+                (_, character) => {
+                    begin_argument(&mut output, &mut started);
+                    argument.push(character);
+                }
+            }
+        }
+    }
+
+    if started && (!argument.is_empty() || written == 0) {
+        output.push(Token::Text(argument));
+    }
+
+    output
+}
+
+/// Arguments are separated from the previous argument once the next argument begins.
+fn begin_argument(output: &mut Vec<Token>, started: &mut bool) {
+    if !*started {
+        if !output.is_empty() {
+            output.push(Token::Break);
+        }
+        *started = true;
+    }
+}
+
+/// Parses the contents of a brace group, such as the `#` within `{#}`.
+fn parse_token(token: &str) -> Option<Token> {
+    match token {
+        "" => Some(Token::Placeholder),
+        "=raw=" => Some(Token::Raw),
+        "%" => Some(Token::Slot),
+        "#" => Some(Token::Job),
+        "." => Some(Token::NoExtension),
+        "/" => Some(Token::Basename),
+        "//" => Some(Token::Dirname),
+        "/." => Some(Token::BasenameNoExtension),
+        "host" => Some(Token::Host),
+        _ => match token.parse::<usize>() {
+            Ok(position) if position != 0 => Some(Token::Positional(position)),
+            _ => None,
+        },
     }
 }
 
@@ -85,8 +250,19 @@ impl Display for Tokens {
         for token in &self.tokens {
             match *token {
                 Token::Placeholder => f.write_str("{}")?,
+                Token::Raw => f.write_str("{=raw=}")?,
                 Token::Slot => f.write_str("{%}")?,
                 Token::Job => f.write_str("{#}")?,
+                Token::Positional(position) => write!(f, "{{{}}}", position)?,
+                Token::NoExtension => f.write_str("{.}")?,
+                Token::Basename => f.write_str("{/}")?,
+                Token::Dirname => f.write_str("{//}")?,
+                Token::BasenameNoExtension => f.write_str("{/.}")?,
+                Token::Host => f.write_str("{host}")?,
+                Token::Break => f.write_str(" ")?,
+                Token::Text(ref string) if self.mode == Mode::Exec => {
+                    write_argument(f, string)?
+                }
                 Token::Text(ref string) => f.write_str(string)?,
             }
         }
@@ -94,9 +270,23 @@ impl Display for Tokens {
     }
 }
 
+/// Quotes text that was originally quoted or escaped, so that the `Exec` mode can be displayed.
+/// Braces are escaped outside of the quotes, as quotes do not prevent a brace group from being
+/// parsed as a token when the command is parsed again, such as by a node.
+fn write_argument(f: &mut Formatter, text: &str) -> fmt::Result {
+    let needs_quotes = |character: char| character.is_whitespace() || "'\"\\".contains(character);
+    if !text.is_empty() && !text.contains(needs_quotes) {
+        return f.write_str(&text.replace('{', "\\{"));
+    }
+
+    f.write_str("'")?;
+    f.write_str(&text.replace('\'', "'\\''").replace('{', "'\\{'"))?;
+    f.write_str("'")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Token, Tokens};
+    use super::{Mode, Token, Tokens};
 
     #[test]
     fn tokens() {
@@ -107,9 +297,124 @@ mod tests {
                 Token::Text(": ".into()),
                 Token::Placeholder,
             ],
+            mode:   Mode::Shell,
         };
 
         assert_eq!(Tokens::new("echo {#}: {}"), expected);
         assert_eq!(Tokens::new("echo {#}:"), expected);
+
+        // Templates are not given a placeholder.
+        let expected = Tokens {
+            tokens: vec![Token::Host, Token::Text(": ".into()), Token::Job],
+            mode:   Mode::Shell,
+        };
+
+        let tokens = Tokens::template("{host}: {#}");
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens.to_string(), "{host}: {#}");
+    }
+
+    #[test]
+    fn positional() {
+        let expected = Tokens {
+            tokens: vec![
+                Token::Text("cp ".into()),
+                Token::Positional(2),
+                Token::Text(" ".into()),
+                Token::Positional(1),
+                Token::Text(" {0}".into()),
+            ],
+            mode:   Mode::Shell,
+        };
+
+        let tokens = Tokens::new("cp {2} {1} {0}");
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens.to_string(), "cp {2} {1} {0}");
+    }
+
+    #[test]
+    fn raw() {
+        let expected = Tokens {
+            tokens: vec![Token::Text("sh -c ".into()), Token::Raw],
+            mode:   Mode::Shell,
+        };
+
+        let tokens = Tokens::new("sh -c {=raw=}");
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens.to_string(), "sh -c {=raw=}");
+    }
+
+    #[test]
+    fn paths() {
+        let expected = Tokens {
+            tokens: vec![
+                Token::Text("convert ".into()),
+                Token::Basename,
+                Token::Text(" ".into()),
+                Token::Dirname,
+                Token::Text("/".into()),
+                Token::BasenameNoExtension,
+                Token::Text(" ".into()),
+                Token::NoExtension,
+                Token::Text(".png".into()),
+            ],
+            mode:   Mode::Shell,
+        };
+
+        let tokens = Tokens::new("convert {/} {//}/{/.} {.}.png");
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens.to_string(), "convert {/} {//}/{/.} {.}.png");
+    }
+
+    #[test]
+    fn exec() {
+        let expected = Tokens {
+            tokens: vec![
+                Token::Text("printf".into()),
+                Token::Break,
+                Token::Text("%s: %s\\n".into()),
+                Token::Break,
+                Token::Text("".into()),
+                Token::Break,
+                Token::Text("a b".into()),
+                Token::Placeholder,
+                Token::Text(".png".into()),
+                Token::Break,
+                Token::Job,
+            ],
+            mode:   Mode::Exec,
+        };
+
+        let tokens = Tokens::with_mode("printf  '%s: %s\\n' \"\" a\\ b{}.png {#}", Mode::Exec);
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens.to_string(), "printf '%s: %s\\n' '' 'a b'{}.png {#}");
+
+        let expected = Tokens {
+            tokens: vec![Token::Text("ls".into()), Token::Break, Token::Placeholder],
+            mode:   Mode::Exec,
+        };
+
+        assert_eq!(Tokens::with_mode("ls ", Mode::Exec), expected);
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let tokens = Tokens::with_mode("echo \\{} 'a b'\\{#} {}", Mode::Exec);
+        assert_eq!(
+            tokens.tokens,
+            vec![
+                Token::Text("echo".into()),
+                Token::Break,
+                Token::Text("{}".into()),
+                Token::Break,
+                Token::Text("a b{#}".into()),
+                Token::Break,
+                Token::Placeholder,
+            ]
+        );
+
+        // Escaped braces remain text once the command has been parsed again.
+        assert_eq!(tokens.to_string(), "echo \\{} 'a b'\\{'#}' {}");
+        assert_eq!(Tokens::with_mode(&tokens.to_string(), Mode::Exec), tokens);
     }
 }